@@ -0,0 +1,16 @@
+package net.dblsaiko.origami.ojavac;
+
+/**
+ * Entry point for running the compiler in a JVM that is shared with other
+ * programs, such as the origami task dispatcher. Unlike {@link Main}, this
+ * never calls {@link System#exit}, which would take down everything else
+ * running in the JVM. A failed compilation throws an exception instead, and
+ * does not write the dependency manifest.
+ */
+public class EmbeddedMain {
+    public static void main(String[] args) {
+        if (!Main.run(args)) {
+            throw new RuntimeException("compilation failed");
+        }
+    }
+}
@@ -14,6 +14,17 @@ import javax.tools.ToolProvider;
 
 public class Main {
     public static void main(String[] args) {
+        if (!run(args)) {
+            System.exit(1);
+        }
+    }
+
+    /**
+     * Runs the compiler with the given arguments.
+     *
+     * @return whether the compilation succeeded
+     */
+    public static boolean run(String[] args) {
         String classpath = args[args.length - 5];
         int optionsLen = Integer.parseInt(args[args.length - 4]);
         int compilationUnitsLen = Integer.parseInt(args[args.length - 3]);
@@ -41,7 +52,7 @@ public class Main {
         boolean result = task.call();
 
         if (!result) {
-            System.exit(1);
+            return false;
         }
 
         if (!manifestPath.isBlank()) {
@@ -51,6 +62,8 @@ public class Main {
         if (!makeManifestPath.isBlank()) {
             writeMakeManifest(iterSlice(args, optionsLen, optionsLen + compilationUnitsLen), fm, Path.of(makeManifestPath));
         }
+
+        return true;
     }
 
     private static <T> Iterable<T> iterSlice(T[] array, int start, int endExclusive) {
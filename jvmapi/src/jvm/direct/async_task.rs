@@ -30,7 +30,7 @@ impl AsyncJvmTask for Task {
     fn wait(&mut self) -> BoxFuture<'_, io::Result<Self::ExitStatus>> {
         self.interface
             .wait(self.id, None)
-            .map(|_timeout| Ok(()))
+            .map(|timeout| timeout.map(|_| ()).ok_or_else(jvm_exited))
             .boxed()
     }
 
@@ -98,3 +98,11 @@ impl AsyncRead for Stdout {
         this.inner.poll_read(cx, buf)
     }
 }
+
+/// Returns the error for waiting on a task in a JVM that exited.
+pub(super) fn jvm_exited() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "the JVM exited before the task finished",
+    )
+}
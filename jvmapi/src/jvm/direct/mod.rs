@@ -1,6 +1,9 @@
+use std::io;
 use std::ops::Deref;
 use std::path::Path;
+use std::time::Duration;
 
+use async_std::future;
 use async_std::task;
 use async_std::task::JoinHandle;
 use futures::executor::block_on;
@@ -65,6 +68,42 @@ impl DirectJvm {
             _lib_path: lib_path,
         })
     }
+
+    /// Like [`DirectJvm::spawn()`], but also waits up to `timeout` for the
+    /// dispatcher to respond. If it doesn't, for example because the JVM
+    /// failed to start, the JVM gets shut down and this returns an error
+    /// instead of tasks waiting for it forever.
+    pub fn spawn_timeout(host: ProcessJvm, timeout: Duration) -> Result<Self, Error> {
+        let jvm = DirectJvm::spawn(host)?;
+
+        // Waiting for a task that doesn't exist returns right away, which
+        // makes for a cheap way of checking that the dispatcher is running.
+        let ping = jvm.interface.wait(u32::MAX, Some(Duration::ZERO));
+
+        match block_on(future::timeout(timeout, ping)) {
+            Ok(Some(_)) => Ok(jvm),
+            Ok(None) => {
+                jvm.shutdown().map_err(Error::Io)?;
+                Err(Error::Failure("the JVM exited while starting".to_string()))
+            }
+            Err(_) => {
+                jvm.shutdown().map_err(Error::Io)?;
+                Err(Error::Failure(format!(
+                    "the JVM did not start within {} seconds",
+                    timeout.as_secs()
+                )))
+            }
+        }
+    }
+
+    /// Stops the JVM, killing any tasks still running in it.
+    pub fn shutdown(mut self) -> io::Result<()> {
+        // the process may have exited on its own already
+        let _ = self.process.kill();
+        block_on(self.process.wait())?;
+        block_on(self.task);
+        Ok(())
+    }
 }
 
 impl Jvm for DirectJvm {
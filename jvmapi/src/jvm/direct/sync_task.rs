@@ -25,8 +25,10 @@ impl JvmTask for Task {
 
     /// Wait for the task to exit.
     fn wait(&mut self) -> io::Result<Self::ExitStatus> {
-        block_on(self.interface.wait(self.id, None));
-        Ok(())
+        match block_on(self.interface.wait(self.id, None)) {
+            Some(_) => Ok(()),
+            None => Err(async_task::jvm_exited()),
+        }
     }
 
     /// The standard output stream, if the process was spawned with
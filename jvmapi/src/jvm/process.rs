@@ -199,6 +199,13 @@ pub struct AsyncJvmProcess {
     process: async_process::Child,
 }
 
+impl AsyncJvmProcess {
+    /// Forces the process to exit.
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.process.kill()
+    }
+}
+
 impl AsyncJvmTask for AsyncJvmProcess {
     type Stdout = async_process::ChildStdout;
     type Stderr = async_process::ChildStderr;
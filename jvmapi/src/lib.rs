@@ -15,6 +15,8 @@ const PATH_SEPARATOR: &str = ";";
 
 #[cfg(test)]
 mod test {
+    use std::time::Duration;
+
     use crate::jvm::command::JvmCommand;
     use crate::jvm::direct::DirectJvm;
     use crate::jvm::process::ProcessJvm;
@@ -32,4 +34,21 @@ mod test {
 
         task.wait().expect("Failed to wait for task to exit");
     }
+
+    #[test]
+    fn spawn_jvm_timeout() {
+        let jvm = ProcessJvm::new();
+        let jvm =
+            DirectJvm::spawn_timeout(jvm, Duration::from_secs(60)).expect("Failed to spawn JVM");
+
+        jvm.shutdown().expect("Failed to shut down JVM");
+    }
+
+    #[test]
+    fn spawn_jvm_fails_to_start() {
+        let mut jvm = ProcessJvm::new();
+        jvm.with_java_arg("-XX:+ThisOptionDoesNotExist");
+
+        assert!(DirectJvm::spawn_timeout(jvm, Duration::from_secs(60)).is_err());
+    }
 }
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::future::Future;
 use std::io;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex};
@@ -22,12 +23,16 @@ use crate::protocol::structs::{
 #[derive(Debug)]
 struct AsyncQueueInner<T> {
     queue: HashMap<u32, channel::Sender<T>>,
+    /// Set once the connection is closed, after which no responses arrive
+    /// anymore.
+    closed: bool,
 }
 
 impl<T> Default for AsyncQueueInner<T> {
     fn default() -> Self {
         AsyncQueueInner {
             queue: Default::default(),
+            closed: false,
         }
     }
 }
@@ -52,10 +57,20 @@ impl<T> AsyncQueue<T> {
         }
     }
 
-    pub async fn start_callback(&self, tag: u32) -> T {
+    /// Registers `tag` as expecting a response and returns a future for it.
+    /// This needs to be called before sending the message the response is
+    /// for, since a response nobody is waiting for yet gets dropped. The
+    /// future resolves to `None` if the connection closes before the
+    /// response arrives.
+    pub fn start_callback(&self, tag: u32) -> impl Future<Output = Option<T>> {
         let (tx, rx) = channel::bounded(1);
-        self.inner.lock().unwrap().queue.insert(tag, tx);
-        rx.recv().await.unwrap()
+        let mut inner = self.inner.lock().unwrap();
+
+        if !inner.closed {
+            inner.queue.insert(tag, tx);
+        }
+
+        async move { rx.recv().await.ok() }
     }
 
     pub async fn finish_callback(&self, tag: u32, msg: T) {
@@ -65,6 +80,14 @@ impl<T> AsyncQueue<T> {
             let _ = tx.send(msg).await;
         }
     }
+
+    /// Stops waiting for responses, resolving every pending callback to
+    /// `None`.
+    pub fn close(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.closed = true;
+        inner.queue.clear();
+    }
 }
 
 struct Shared<T> {
@@ -206,6 +229,10 @@ where
                 }
             }
         }
+
+        // the JVM exited, so nobody is going to answer anymore
+        self.shared.exec.close();
+        self.shared.wait.close();
     }
 }
 
@@ -286,9 +313,16 @@ where
 
         let cb = self.shared.exec.start_callback(tag);
 
-        self.shared.sink.lock().await.send(packet).await.unwrap();
+        if self.shared.sink.lock().await.send(packet).await.is_err() {
+            return Err(jvm_exited());
+        }
+
+        let result = match cb.await {
+            Some(response) => response.result,
+            None => return Err(jvm_exited()),
+        };
 
-        match cb.await.result {
+        match result {
             Ok(ti) => Ok(TaskData {
                 task_id: ti.task_id,
                 stdin: if_normal(stdin),
@@ -300,18 +334,25 @@ where
     }
 
     /// Sends a [`Wait`] message to the JVM and waits for the response.
-    pub async fn wait(&self, task: u32, timeout: Option<Duration>) -> bool {
+    /// Returns whether the wait timed out, or `None` if the JVM exited.
+    pub async fn wait(&self, task: u32, timeout: Option<Duration>) -> Option<bool> {
         let tag = self.tag.fetch_add(1, Ordering::Relaxed);
         let packet = ToJvm::Wait(Wait { tag, task, timeout });
 
         let cb = self.shared.wait.start_callback(tag);
 
-        self.shared.sink.lock().await.send(packet).await.unwrap();
+        if self.shared.sink.lock().await.send(packet).await.is_err() {
+            return None;
+        }
 
-        cb.await.timeout
+        cb.await.map(|el| el.timeout)
     }
 }
 
+fn jvm_exited() -> ExecError {
+    ExecError::Failure("the JVM exited".to_string())
+}
+
 pub fn create<T, U>(source: T, sink: U) -> (JvmInterface<U>, PacketHandler<T, U>) {
     let shared = Shared {
         sink: Arc::new(AsyncMutex::new(sink)),
@@ -0,0 +1,584 @@
+//! Hashing of the part of a class file other classes compile against.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+const ACC_PRIVATE: u16 = 0x0002;
+
+/// Returns a hash of everything in the given class files that classes using
+/// them can depend on: the class declarations with their non-private nested
+/// classes, permitted subclasses and record components, and the names,
+/// types, generic signatures, constant values, thrown exceptions and
+/// annotations of all non-private fields and methods. Method bodies and
+/// private members don't affect it, so changing them doesn't require
+/// recompiling other classes.
+pub fn abi_hash<P: AsRef<Path>>(class_files: &[P]) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+
+    for path in class_files {
+        let content = fs::read(path)?;
+
+        for line in class_abi(&content)? {
+            hasher.update(&line);
+            hasher.update(b"\n");
+        }
+    }
+
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Returns the ABI of a single class as a list of lines, one for the class
+/// itself and one for each visible member, sorted so that reordering members
+/// doesn't change it. Private nested classes don't have any.
+fn class_abi(content: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let mut r = Reader { buf: content };
+
+    if r.u4()? != 0xCAFEBABE {
+        return Err(invalid("not a class file"));
+    }
+
+    // minor and major version
+    r.skip(4)?;
+
+    let cp = ConstantPool::read(&mut r)?;
+
+    let mut access = r.u2()?;
+    let this_class = cp.class_name(r.u2()?)?;
+    let super_class = match r.u2()? {
+        0 => &[][..],
+        idx => cp.class_name(idx)?,
+    };
+
+    let mut interfaces = Vec::new();
+
+    for _ in 0..r.u2()? {
+        interfaces.push(cp.class_name(r.u2()?)?);
+    }
+
+    interfaces.sort_unstable();
+
+    let mut members = Vec::new();
+
+    for kind in [&b"field"[..], &b"method"[..]] {
+        for _ in 0..r.u2()? {
+            let mut access = r.u2()?;
+            let name = cp.utf8(r.u2()?)?;
+            let descriptor = cp.utf8(r.u2()?)?;
+            let attributes = read_attributes(&mut r, &cp, this_class, &mut access)?;
+
+            if access & ACC_PRIVATE != 0 {
+                continue;
+            }
+
+            let mut line = Vec::new();
+            line.extend_from_slice(kind);
+            line.extend_from_slice(format!(" {:04x} ", access).as_bytes());
+            line.extend_from_slice(name);
+            line.push(b' ');
+            line.extend_from_slice(descriptor);
+            line.extend(attributes);
+            members.push(line);
+        }
+    }
+
+    members.sort_unstable();
+
+    let attributes = read_attributes(&mut r, &cp, this_class, &mut access)?;
+
+    if access & ACC_PRIVATE != 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut line = Vec::new();
+    line.extend_from_slice(format!("class {:04x} ", access).as_bytes());
+    line.extend_from_slice(this_class);
+    line.extend_from_slice(b" extends ");
+    line.extend_from_slice(super_class);
+    line.extend_from_slice(b" implements");
+
+    for interface in interfaces {
+        line.push(b' ');
+        line.extend_from_slice(interface);
+    }
+
+    line.extend(attributes);
+
+    let mut lines = vec![line];
+    lines.extend(members);
+    Ok(lines)
+}
+
+/// Reads an attribute table and returns the parts of it relevant to the ABI.
+/// `this_class` is the name of the class the attributes belong to. If it's a
+/// nested class, `access` gets replaced with the modifiers it was declared
+/// with, which the class file doesn't have otherwise.
+fn read_attributes(
+    r: &mut Reader,
+    cp: &ConstantPool,
+    this_class: &[u8],
+    access: &mut u16,
+) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+
+    for _ in 0..r.u2()? {
+        let name = cp.utf8(r.u2()?)?;
+        let len = r.u4()? as usize;
+        let mut data = Reader { buf: r.take(len)? };
+
+        match name {
+            b"Signature" => {
+                out.extend_from_slice(b" signature ");
+                out.extend_from_slice(cp.utf8(data.u2()?)?);
+            }
+            b"ConstantValue" => {
+                // constants get inlined into the classes using them
+                out.extend_from_slice(b" value ");
+                out.extend_from_slice(&cp.constant(data.u2()?)?);
+            }
+            b"Exceptions" => {
+                out.extend_from_slice(b" throws");
+
+                for _ in 0..data.u2()? {
+                    out.push(b' ');
+                    out.extend_from_slice(cp.class_name(data.u2()?)?);
+                }
+            }
+            b"InnerClasses" => {
+                // This lists every nested class the class refers to, also
+                // from method bodies, so only keep the ones it declares.
+                let mut classes = Vec::new();
+
+                for _ in 0..data.u2()? {
+                    let inner = cp.class_name(data.u2()?)?;
+                    let outer = match data.u2()? {
+                        0 => &[][..],
+                        idx => cp.class_name(idx)?,
+                    };
+                    data.skip(2)?;
+                    let inner_access = data.u2()?;
+
+                    if inner == this_class {
+                        *access = inner_access;
+                    } else if outer == this_class && inner_access & ACC_PRIVATE == 0 {
+                        let mut class = format!(" inner {:04x} ", inner_access).into_bytes();
+                        class.extend_from_slice(inner);
+                        classes.push(class);
+                    }
+                }
+
+                classes.sort_unstable();
+                classes.into_iter().for_each(|el| out.extend(el));
+            }
+            b"PermittedSubclasses" => {
+                let mut classes = Vec::new();
+
+                for _ in 0..data.u2()? {
+                    classes.push(cp.class_name(data.u2()?)?);
+                }
+
+                classes.sort_unstable();
+                out.extend_from_slice(b" permits");
+
+                for class in classes {
+                    out.push(b' ');
+                    out.extend_from_slice(class);
+                }
+            }
+            b"Record" => {
+                out.extend_from_slice(b" record");
+
+                for _ in 0..data.u2()? {
+                    out.extend_from_slice(b" (");
+                    out.extend_from_slice(cp.utf8(data.u2()?)?);
+                    out.push(b' ');
+                    out.extend_from_slice(cp.utf8(data.u2()?)?);
+                    out.extend(read_attributes(&mut data, cp, this_class, &mut 0)?);
+                    out.push(b')');
+                }
+            }
+            b"RuntimeVisibleAnnotations" | b"RuntimeInvisibleAnnotations" => {
+                out.push(b' ');
+                out.extend_from_slice(name);
+
+                for _ in 0..data.u2()? {
+                    read_annotation(&mut data, cp, &mut out)?;
+                }
+            }
+            b"RuntimeVisibleParameterAnnotations" | b"RuntimeInvisibleParameterAnnotations" => {
+                out.push(b' ');
+                out.extend_from_slice(name);
+
+                for _ in 0..data.u1()? {
+                    out.extend_from_slice(b" (");
+
+                    for _ in 0..data.u2()? {
+                        read_annotation(&mut data, cp, &mut out)?;
+                    }
+
+                    out.push(b')');
+                }
+            }
+            b"RuntimeVisibleTypeAnnotations" | b"RuntimeInvisibleTypeAnnotations" => {
+                out.push(b' ');
+                out.extend_from_slice(name);
+
+                for _ in 0..data.u2()? {
+                    read_type_annotation(&mut data, cp, &mut out)?;
+                }
+            }
+            b"AnnotationDefault" => {
+                out.extend_from_slice(b" default ");
+                read_element_value(&mut data, cp, &mut out)?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(out)
+}
+
+/// Reads an annotation and appends it to `out` with the constant pool
+/// references resolved, like `@Lnet/example/A;(value=I...)`.
+fn read_annotation(r: &mut Reader, cp: &ConstantPool, out: &mut Vec<u8>) -> io::Result<()> {
+    out.extend_from_slice(b" @");
+    out.extend_from_slice(cp.utf8(r.u2()?)?);
+    out.push(b'(');
+
+    for idx in 0..r.u2()? {
+        if idx > 0 {
+            out.push(b',');
+        }
+
+        out.extend_from_slice(cp.utf8(r.u2()?)?);
+        out.push(b'=');
+        read_element_value(r, cp, out)?;
+    }
+
+    out.push(b')');
+    Ok(())
+}
+
+/// Reads a type annotation. Its target and type path don't refer to the
+/// constant pool, so they are kept as they are.
+fn read_type_annotation(r: &mut Reader, cp: &ConstantPool, out: &mut Vec<u8>) -> io::Result<()> {
+    let target_type = r.u1()?;
+    let target_len = match target_type {
+        0x13..=0x15 => 0,
+        0x00 | 0x01 | 0x16 => 1,
+        0x10 | 0x11 | 0x12 | 0x17 | 0x42..=0x46 => 2,
+        0x47..=0x4b => 3,
+        0x40 | 0x41 => {
+            let count = r.u2()?;
+            r.skip(count as usize * 6)?;
+            0
+        }
+        _ => {
+            return Err(invalid(&format!(
+                "unknown type annotation target {}",
+                target_type
+            )))
+        }
+    };
+    let target = r.take(target_len)?;
+    let path_len = r.u1()? as usize;
+    let path = r.take(path_len * 2)?;
+
+    out.extend_from_slice(format!(" {:02x}", target_type).as_bytes());
+    target
+        .iter()
+        .chain(path)
+        .for_each(|b| out.extend_from_slice(format!("{:02x}", b).as_bytes()));
+    read_annotation(r, cp, out)
+}
+
+/// Reads the value of an annotation element.
+fn read_element_value(r: &mut Reader, cp: &ConstantPool, out: &mut Vec<u8>) -> io::Result<()> {
+    let tag = r.u1()?;
+    out.push(tag);
+
+    match tag {
+        b's' => out.extend_from_slice(cp.utf8(r.u2()?)?),
+        b'B' | b'C' | b'D' | b'F' | b'I' | b'J' | b'S' | b'Z' => {
+            out.extend_from_slice(&cp.constant(r.u2()?)?)
+        }
+        b'e' => {
+            out.extend_from_slice(cp.utf8(r.u2()?)?);
+            out.push(b'.');
+            out.extend_from_slice(cp.utf8(r.u2()?)?);
+        }
+        b'c' => out.extend_from_slice(cp.utf8(r.u2()?)?),
+        b'@' => read_annotation(r, cp, out)?,
+        b'[' => {
+            out.push(b'{');
+
+            for idx in 0..r.u2()? {
+                if idx > 0 {
+                    out.push(b',');
+                }
+
+                read_element_value(r, cp, out)?;
+            }
+
+            out.push(b'}');
+        }
+        _ => return Err(invalid(&format!("unknown element value tag {}", tag))),
+    }
+
+    Ok(())
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.buf.len() < len {
+            return Err(invalid("unexpected end of class file"));
+        }
+
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn skip(&mut self, len: usize) -> io::Result<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u1(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u2(&mut self) -> io::Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u4(&mut self) -> io::Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+enum Constant<'a> {
+    Utf8(&'a [u8]),
+    Class(u16),
+    String(u16),
+    /// Integer, float, long and double constants.
+    Number(&'a [u8]),
+    Other,
+    /// The unusable slot after a long or double constant.
+    Unused,
+}
+
+struct ConstantPool<'a> {
+    entries: Vec<Constant<'a>>,
+}
+
+impl<'a> ConstantPool<'a> {
+    fn read(r: &mut Reader<'a>) -> io::Result<Self> {
+        let count = r.u2()?;
+
+        // index 0 isn't used
+        let mut entries = vec![Constant::Unused];
+
+        while entries.len() < count as usize {
+            let entry = match r.u1()? {
+                1 => {
+                    let len = r.u2()? as usize;
+                    Constant::Utf8(r.take(len)?)
+                }
+                3 | 4 => Constant::Number(r.take(4)?),
+                5 | 6 => {
+                    entries.push(Constant::Number(r.take(8)?));
+                    Constant::Unused
+                }
+                7 => Constant::Class(r.u2()?),
+                8 => Constant::String(r.u2()?),
+                16 | 19 | 20 => {
+                    r.skip(2)?;
+                    Constant::Other
+                }
+                15 => {
+                    r.skip(3)?;
+                    Constant::Other
+                }
+                9 | 10 | 11 | 12 | 17 | 18 => {
+                    r.skip(4)?;
+                    Constant::Other
+                }
+                tag => return Err(invalid(&format!("unknown constant pool tag {}", tag))),
+            };
+
+            entries.push(entry);
+        }
+
+        Ok(ConstantPool { entries })
+    }
+
+    fn get(&self, idx: u16) -> io::Result<&Constant<'a>> {
+        self.entries
+            .get(idx as usize)
+            .ok_or_else(|| invalid("constant pool index out of bounds"))
+    }
+
+    fn utf8(&self, idx: u16) -> io::Result<&'a [u8]> {
+        match self.get(idx)? {
+            Constant::Utf8(s) => Ok(s),
+            _ => Err(invalid("expected a UTF-8 constant")),
+        }
+    }
+
+    fn class_name(&self, idx: u16) -> io::Result<&'a [u8]> {
+        match self.get(idx)? {
+            Constant::Class(name) => self.utf8(*name),
+            _ => Err(invalid("expected a class constant")),
+        }
+    }
+
+    fn constant(&self, idx: u16) -> io::Result<Vec<u8>> {
+        match self.get(idx)? {
+            Constant::Number(bytes) => Ok(bytes.to_vec()),
+            Constant::String(s) => Ok(self.utf8(*s)?.to_vec()),
+            _ => Err(invalid("expected a constant value")),
+        }
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+
+    use super::abi_hash;
+
+    /// Compiles `source`, a file declaring the class `A`, and returns the
+    /// ABI hash of all classes compiled from it.
+    fn abi(source: &str) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("A.java");
+        let out = dir.path().join("out");
+        fs::write(&src, source).unwrap();
+
+        let status = Command::new("javac")
+            .arg("-d")
+            .arg(&out)
+            .arg(&src)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let mut class_files: Vec<_> = fs::read_dir(&out)
+            .unwrap()
+            .map(|el| el.unwrap().path())
+            .collect();
+        class_files.sort();
+        abi_hash(&class_files).unwrap()
+    }
+
+    #[test]
+    fn ignores_bodies_and_private_members() {
+        let base = abi("public class A { public int f() { return 1; } }");
+
+        assert_eq!(abi("public class A { public int f() { return 2; } }"), base);
+        assert_eq!(
+            abi("public class A { private int x; public int f() { return x; } private void g() {} }"),
+            base
+        );
+        assert_eq!(
+            abi("public class A { public int f() { return java.util.Map.entry(1, 2).getKey(); } }"),
+            base
+        );
+        assert_eq!(
+            abi("public class A { public int f() { return 1; } private static class B {} }"),
+            base
+        );
+    }
+
+    #[test]
+    fn depends_on_signatures() {
+        let base = abi("public class A { public int f() { return 1; } }");
+
+        assert_ne!(
+            abi("public class A { public long f() { return 1; } }"),
+            base
+        );
+        assert_ne!(
+            abi("public class A { public int f() { return 1; } public int x; }"),
+            base
+        );
+        assert_ne!(
+            abi("public class A { public static final int X = 1; }"),
+            abi("public class A { public static final int X = 2; }")
+        );
+        assert_ne!(
+            abi("public class A { public java.util.List<String> f() { return null; } }"),
+            abi("public class A { public java.util.List<Integer> f() { return null; } }")
+        );
+    }
+
+    #[test]
+    fn depends_on_annotations() {
+        let base = abi("public class A { public void f() {} }");
+
+        assert_ne!(
+            abi("@Deprecated public class A { public void f() {} }"),
+            base
+        );
+        assert_ne!(
+            abi("public class A { @Deprecated public void f() {} }"),
+            base
+        );
+        assert_ne!(
+            abi("public class A { @Deprecated(since = \"1\") public void f() {} }"),
+            abi("public class A { @Deprecated(since = \"2\") public void f() {} }")
+        );
+        assert_ne!(
+            abi("public @interface A { int value() default 1; }"),
+            abi("public @interface A { int value() default 2; }")
+        );
+    }
+
+    #[test]
+    fn depends_on_nested_classes() {
+        let base = abi("public class A {}");
+
+        assert_ne!(abi("public class A { public static class B {} }"), base);
+        assert_ne!(
+            abi("public class A { public static class B {} }"),
+            abi("public class A { public class B {} }")
+        );
+    }
+
+    #[test]
+    fn depends_on_permitted_subclasses() {
+        assert_ne!(
+            abi("public sealed class A permits A.B { public static final class B extends A {} }"),
+            abi("public class A { public static final class B extends A {} }")
+        );
+    }
+
+    #[test]
+    fn depends_on_record_components() {
+        assert_ne!(
+            abi("public record A(int x) {}"),
+            abi("public record A(long x) {}")
+        );
+        assert_ne!(
+            abi("public record A(int x) {}"),
+            abi("public record A(int y) {}")
+        );
+        assert_eq!(
+            abi("public record A(int x) { public int x() { return x; } }"),
+            abi("public record A(int x) { public int x() { return x + 0; } }")
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use zip::write::FileOptions;
+use zip::{CompressionMethod, DateTime, ZipWriter};
+
+use crate::task::{input_changed_multi, Context, PathDep, StaleReason, Task, TaskError};
+
+/// Packages the files matched by a set of directory dependencies into a jar.
+/// The jar only depends on the contents of the input files: entries are
+/// sorted by name and all timestamps are set to the same fixed date, so
+/// building it twice from the same files yields the same bytes.
+#[derive(Debug)]
+pub struct JarTask {
+    inputs: Vec<PathDep>,
+    output: PathBuf,
+    main_class: Option<String>,
+}
+
+impl Task for JarTask {
+    fn name(&self) -> String {
+        "jar".to_string()
+    }
+
+    fn make(&self, _ctx: &Context) -> Result<(), TaskError> {
+        if let Some(dir) = self.output.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // Write to a temporary file first so that a failed build doesn't
+        // leave behind a truncated jar that looks up to date.
+        let mut temp_path = self.output.clone().into_os_string();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+
+        match self.write_jar(&temp_path) {
+            Ok(()) => fs::rename(&temp_path, &self.output)?,
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                return Err(e.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn inputs(&self, _ctx: &Context) -> Vec<PathDep> {
+        self.inputs.clone()
+    }
+
+    fn outputs(&self, _ctx: &Context) -> Vec<PathDep> {
+        vec![PathDep::new(self.output.clone())]
+    }
+
+    fn needs_exec(&self, ctx: &Context) -> Option<StaleReason> {
+        input_changed_multi(&self.inputs(ctx), &self.outputs(ctx)).unwrap_or_else(|e| Some(e.into()))
+    }
+
+    fn fingerprint(&self, _ctx: &Context) -> Option<String> {
+        // the input files are covered by the recorded state, but not which
+        // directories they get placed in the jar relative to
+        let mut s = self.manifest();
+
+        for input in &self.inputs {
+            s.push_str(&input.base().to_string_lossy());
+            s.push('\n');
+        }
+
+        Some(s)
+    }
+
+    fn commands(&self, _ctx: &Context) -> Vec<String> {
+        let inputs: Vec<_> = self
+            .inputs
+            .iter()
+            .map(|el| format!("'{}'", el.base().display()))
+            .collect();
+
+        vec![format!(
+            "write jar '{}' from {}",
+            self.output.display(),
+            inputs.join(", ")
+        )]
+    }
+}
+
+impl JarTask {
+    pub fn new(inputs: Vec<PathDep>, output: PathBuf, main_class: Option<String>) -> Self {
+        JarTask {
+            inputs,
+            output,
+            main_class,
+        }
+    }
+
+    fn write_jar(&self, path: &Path) -> io::Result<()> {
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(DateTime::default());
+
+        let mut zip = ZipWriter::new(BufWriter::new(File::create(path)?));
+
+        // The manifest has to be the first file in the jar.
+        zip.add_directory("META-INF/", options)?;
+        zip.start_file("META-INF/MANIFEST.MF", options)?;
+        zip.write_all(self.manifest().as_bytes())?;
+
+        for (name, path) in self.collect_entries() {
+            // already written above
+            if name == "META-INF/" || name.eq_ignore_ascii_case("META-INF/MANIFEST.MF") {
+                continue;
+            }
+
+            match path {
+                None => zip.add_directory(name, options)?,
+                Some(path) => {
+                    zip.start_file(name, options)?;
+                    io::copy(&mut File::open(path)?, &mut zip)?;
+                }
+            }
+        }
+
+        zip.finish()?.flush()?;
+
+        Ok(())
+    }
+
+    fn manifest(&self) -> String {
+        let mut s = String::new();
+        s.push_str("Manifest-Version: 1.0\r\n");
+        s.push_str("Created-By: omake\r\n");
+
+        if let Some(main_class) = &self.main_class {
+            s.push_str(&format!("Main-Class: {}\r\n", main_class));
+        }
+
+        s.push_str("\r\n");
+        s
+    }
+
+    /// Returns the entries of the jar, sorted by name, along with the file
+    /// they're read from. Directories map to `None`.
+    fn collect_entries(&self) -> BTreeMap<String, Option<PathBuf>> {
+        let mut entries = BTreeMap::new();
+
+        for input in &self.inputs {
+            for path in input.paths() {
+                let rel = match path.strip_prefix(input.base()) {
+                    Ok(rel) => rel,
+                    Err(_) => continue,
+                };
+
+                let components: Vec<_> = rel
+                    .iter()
+                    .map(|el| el.to_string_lossy().into_owned())
+                    .collect();
+
+                for idx in 1..components.len() {
+                    let dir = format!("{}/", components[..idx].join("/"));
+                    entries.entry(dir).or_insert(None);
+                }
+
+                // earlier inputs take precedence over later ones
+                entries
+                    .entry(components.join("/"))
+                    .or_insert_with(|| Some(path.to_path_buf()));
+            }
+        }
+
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::JarTask;
+    use crate::task::PathDep;
+    use crate::testutil::write;
+
+    #[test]
+    fn jar_only_depends_on_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let classes = dir.path().join("classes");
+        let resources = dir.path().join("resources");
+        let files = [
+            (classes.join("p/A.class"), "a"),
+            (classes.join("p/q/B.class"), "b"),
+            (resources.join("assets/c.json"), "{}"),
+        ];
+
+        let task = JarTask::new(
+            vec![
+                PathDep::new(classes).with_dir(true, "*.class"),
+                PathDep::new(resources).with_dir(true, "*"),
+            ],
+            dir.path().join("p.jar"),
+            Some("p.A".to_string()),
+        );
+
+        let mut jars = Vec::new();
+
+        for idx in 0..2 {
+            // Write the files again with different timestamps. Timestamps in
+            // a jar have a resolution of two seconds.
+            if idx > 0 {
+                thread::sleep(Duration::from_millis(2100));
+            }
+
+            for (path, content) in &files {
+                write(path, content);
+            }
+
+            let path = dir.path().join(format!("{}.jar", idx));
+            task.write_jar(&path).unwrap();
+            jars.push(fs::read(&path).unwrap());
+        }
+
+        assert!(jars[0] == jars[1], "the jars differ");
+    }
+}
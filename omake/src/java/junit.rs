@@ -0,0 +1,390 @@
+use std::ffi::OsStr;
+use std::path::Path;
+use std::{fs, io};
+
+/// The results of a test run, read from the JUnit XML reports it wrote.
+#[derive(Debug, Default)]
+pub struct TestReport {
+    passed: usize,
+    skipped: usize,
+    /// The tests that failed or errored, as `class#method`.
+    failures: Vec<String>,
+}
+
+impl TestReport {
+    /// Reads all reports in `dir`. A missing directory contains no tests.
+    pub fn read_dir(dir: &Path) -> io::Result<Self> {
+        let mut report = TestReport::default();
+
+        let rd = match fs::read_dir(dir) {
+            Ok(rd) => rd,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(report),
+            Err(e) => return Err(e),
+        };
+
+        let mut paths = Vec::new();
+
+        for entry in rd {
+            let path = entry?.path();
+
+            if path.extension() == Some(OsStr::new("xml")) {
+                paths.push(path);
+            }
+        }
+
+        // so that failures are listed in the same order every time
+        paths.sort();
+
+        for path in paths {
+            report.add(&fs::read_to_string(path)?);
+        }
+
+        Ok(report)
+    }
+
+    /// Adds the test cases of a report to this one. This doesn't validate the
+    /// XML, it only looks at the `testcase` elements and the elements
+    /// directly in them.
+    fn add(&mut self, xml: &str) {
+        let mut depth = 0;
+        let mut current: Option<TestCase> = None;
+
+        for tag in Tags::new(xml) {
+            match tag {
+                Tag::Start { name, attrs, empty } => {
+                    match &mut current {
+                        None if name == "testcase" => {
+                            let class = attribute(attrs, "classname").unwrap_or_default();
+                            let name = attribute(attrs, "name").unwrap_or_default();
+
+                            current = Some(TestCase {
+                                id: format!("{}#{}", class, name),
+                                depth,
+                                outcome: Outcome::Passed,
+                            });
+                        }
+                        Some(case) if depth == case.depth + 1 => match name {
+                            "failure" | "error" => case.outcome = Outcome::Failed,
+                            "skipped" if case.outcome == Outcome::Passed => {
+                                case.outcome = Outcome::Skipped
+                            }
+                            _ => {}
+                        },
+                        _ => {}
+                    }
+
+                    if !empty {
+                        depth += 1;
+                    }
+                }
+                Tag::End => depth = depth.saturating_sub(1),
+            }
+
+            if let Some(case) = &current {
+                if depth == case.depth {
+                    let case = current.take().unwrap();
+
+                    match case.outcome {
+                        Outcome::Passed => self.passed += 1,
+                        Outcome::Skipped => self.skipped += 1,
+                        Outcome::Failed => self.failures.push(case.id),
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.passed + self.skipped + self.failures.len()
+    }
+
+    pub fn failures(&self) -> &[String] {
+        &self.failures
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Returns a one-line summary of the results.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} tests completed: {} passed, {} failed, {} skipped",
+            self.total(),
+            self.passed,
+            self.failures.len(),
+            self.skipped
+        )
+    }
+}
+
+/// A `testcase` element being read.
+struct TestCase {
+    /// The test, as `class#method`.
+    id: String,
+    /// How many elements the `testcase` element is in.
+    depth: usize,
+    outcome: Outcome,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Outcome {
+    Passed,
+    Skipped,
+    Failed,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum Tag<'a> {
+    /// A start tag with its name and the text of its attributes, or an
+    /// empty-element tag, which doesn't have a matching end tag.
+    Start {
+        name: &'a str,
+        attrs: &'a str,
+        empty: bool,
+    },
+    End,
+}
+
+/// An iterator over the tags of an XML document, skipping over everything
+/// else, such as text, comments and CDATA sections.
+struct Tags<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Tags<'a> {
+    fn new(xml: &'a str) -> Self {
+        Tags { rest: xml }
+    }
+
+    /// Skips over the rest of the markup starting with `start` and ending
+    /// with `end`, returning whether it did.
+    fn skip(&mut self, start: &str, end: &str) -> bool {
+        if !self.rest.starts_with(start) {
+            return false;
+        }
+
+        self.rest = match self.rest[start.len()..].find(end) {
+            Some(idx) => &self.rest[start.len() + idx + end.len()..],
+            None => "",
+        };
+
+        true
+    }
+}
+
+impl<'a> Iterator for Tags<'a> {
+    type Item = Tag<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.rest = &self.rest[self.rest.find('<')?..];
+
+            if self.skip("<!--", "-->")
+                || self.skip("<![CDATA[", "]]>")
+                || self.skip("<?", "?>")
+                || self.skip("<!", ">")
+            {
+                continue;
+            }
+
+            let len = tag_len(self.rest)?;
+            let tag = &self.rest[1..len - 1];
+            self.rest = &self.rest[len..];
+
+            if tag.starts_with('/') {
+                return Some(Tag::End);
+            }
+
+            let (tag, empty) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+
+            let name_len = tag.find(char::is_whitespace).unwrap_or(tag.len());
+
+            return Some(Tag::Start {
+                name: &tag[..name_len],
+                attrs: &tag[name_len..],
+                empty,
+            });
+        }
+    }
+}
+
+/// Returns the length of the tag `s` starts with, up to and including the
+/// `>` that isn't in an attribute value.
+fn tag_len(s: &str) -> Option<usize> {
+    let mut quote = None;
+
+    for (idx, c) in s.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '>' => return Some(idx + 1),
+            None => {}
+        }
+    }
+
+    None
+}
+
+/// Returns the unescaped value of the attribute `name` in `attrs`, the
+/// attributes of a tag.
+fn attribute(attrs: &str, name: &str) -> Option<String> {
+    let mut rest = attrs;
+
+    loop {
+        let eq = rest.find('=')?;
+        let key = rest[..eq].trim();
+        let value = rest[eq + 1..].trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let len = value[1..].find(quote)?;
+
+        if key == name {
+            return Some(unescape(&value[1..len + 1]));
+        }
+
+        rest = &value[len + 2..];
+    }
+}
+
+/// Replaces the entity and character references in `s` with the characters
+/// they stand for.
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(idx) = rest.find('&') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        let c = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                entity => {
+                    let code = match entity.strip_prefix("#x") {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                        None => entity.strip_prefix('#').and_then(|el| el.parse().ok()),
+                    };
+
+                    code.and_then(char::from_u32)
+                }
+            };
+
+            c.map(|c| (c, end + 1))
+        });
+
+        match c {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            // not a reference, keep it as it is
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TestReport;
+
+    /// Returns the report of the console launcher's legacy XML reporter for
+    /// `cases`, the `testcase` elements.
+    fn report(cases: &str) -> TestReport {
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuite name="JUnit Jupiter" tests="3" skipped="0" failures="0" errors="0" time="0.052" hostname="localhost" timestamp="2022-02-03T18:10:54">
+<properties>
+<property name="java.class.path" value="/build/p/test:/build/p/src"/>
+<property name="line.separator" value="&#10;"/>
+</properties>
+{}
+<system-out><![CDATA[
+unique-id: [engine:junit-jupiter]
+display-name: JUnit Jupiter
+]]></system-out>
+</testsuite>
+"#,
+            cases
+        );
+
+        let mut report = TestReport::default();
+        report.add(&xml);
+        report
+    }
+
+    #[test]
+    fn reads_outcomes() {
+        let report = report(
+            r#"<testcase name="adds()" classname="p.CalcTest" time="0.012">
+<system-out><![CDATA[
+unique-id: [engine:junit-jupiter]/[class:p.CalcTest]/[method:adds()]
+display-name: adds()
+]]></system-out>
+</testcase>
+<testcase name="divides()" classname="p.CalcTest" time="0.003">
+<failure message="expected: &lt;2&gt; but was: &lt;3&gt;" type="org.opentest4j.AssertionFailedError">org.opentest4j.AssertionFailedError: expected: &lt;2&gt; but was: &lt;3&gt;
+	at p.CalcTest.divides(CalcTest.java:14)
+</failure>
+<system-out><![CDATA[
+unique-id: [engine:junit-jupiter]/[class:p.CalcTest]/[method:divides()]
+display-name: divides()
+]]></system-out>
+</testcase>
+<testcase name="parses()" classname="p.CalcTest" time="0.001">
+<error message="null" type="java.lang.NullPointerException">java.lang.NullPointerException
+	at p.CalcTest.parses(CalcTest.java:20)
+</error>
+</testcase>
+<testcase name="rounds()" classname="p.CalcTest" time="0">
+<skipped><![CDATA[public void p.CalcTest.rounds() is @Disabled]]></skipped>
+</testcase>
+<testcase name="empty()" classname="p.OtherTest" time="0"/>"#,
+        );
+
+        assert_eq!(report.total(), 5);
+        assert_eq!(report.passed, 2);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(
+            report.failures(),
+            ["p.CalcTest#divides()", "p.CalcTest#parses()"]
+        );
+    }
+
+    #[test]
+    fn ignores_markup_in_values_and_text() {
+        let report = report(
+            r#"<testcase name="compares(int, int)[1] 2 > 1" classname='p.Cmp&amp;Test' time="0.001">
+<system-out><![CDATA[
+unique-id: [engine:junit-jupiter]/[class:p.Cmp&Test]/[test-template:compares(int, int)]/[test-template-invocation:#1]
+display-name: [1] 2 > 1
+printed: <failure message="not a real one"/>
+]]></system-out>
+</testcase>
+<!-- <testcase name="commented()" classname="p.Cmp&amp;Test"><failure/></testcase> -->
+<testcase name="compares(int, int)[2] 1 &gt; 2" classname='p.Cmp&amp;Test' time="0.002">
+<failure message="a > b" type="org.opentest4j.AssertionFailedError"><![CDATA[</testcase>]]></failure>
+</testcase>"#,
+        );
+
+        assert_eq!(report.total(), 2);
+        assert_eq!(report.passed, 1);
+        assert_eq!(
+            report.failures(),
+            ["p.Cmp&Test#compares(int, int)[2] 1 > 2"]
+        );
+    }
+}
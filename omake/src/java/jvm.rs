@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::io;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use jvmapi::jvm::command::Stdio;
+use jvmapi::jvm::JvmTask;
+use jvmapi::{DirectJvm, JvmCommand, ProcessJvm};
+use log::warn;
+
+use crate::project::Project;
+use crate::task::ExecError;
+
+pub const MAIN_CLASS: &str = "net.dblsaiko.origami.ojavac.EmbeddedMain";
+
+/// How long to wait for a newly started JVM to respond before giving up on
+/// it.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+type Jvms = HashMap<Vec<PathBuf>, Vec<DirectJvm>>;
+
+/// JVMs tasks ran in before, by the class path they were started with. A JVM
+/// is taken out while a task runs in it, so tasks running at the same time
+/// each get their own. All projects of a build share one pool through their
+/// extensions, and [`shutdown()`] stops its JVMs when the build is done.
+#[derive(Default)]
+pub struct JvmPool {
+    jvms: Mutex<Jvms>,
+    /// ojavac's jar, which the compiler JVMs load its classes from. It's
+    /// extracted when the first file is compiled.
+    compiler_jar: Mutex<Option<JarFile>>,
+}
+
+impl JvmPool {
+    /// Runs `f` in a JVM started with `class_path`, taking one from the pool
+    /// or starting a new one. The JVM goes back into the pool afterwards,
+    /// unless `f` failed, which may have left it unusable.
+    pub fn with_jvm<F, R>(&self, class_path: &[PathBuf], f: F) -> Result<R, ExecError>
+    where
+        F: FnOnce(&DirectJvm) -> Result<R, ExecError>,
+    {
+        let pooled = self.jvms()?.get_mut(class_path).and_then(Vec::pop);
+
+        let jvm = match pooled {
+            Some(jvm) => jvm,
+            None => {
+                let mut host = ProcessJvm::new();
+                host.with_classpath(class_path);
+                DirectJvm::spawn_timeout(host, STARTUP_TIMEOUT)?
+            }
+        };
+
+        match f(&jvm) {
+            Ok(v) => {
+                self.jvms()?
+                    .entry(class_path.to_vec())
+                    .or_default()
+                    .push(jvm);
+                Ok(v)
+            }
+            Err(e) => {
+                stop(jvm);
+                Err(e)
+            }
+        }
+    }
+
+    /// Runs ojavac's main class with the given arguments in a compiler JVM,
+    /// and copies its output to our own standard output and error streams.
+    /// Note that the arguments are the ones passed to the Java side of
+    /// ojavac, not the ojavac executable.
+    pub fn run_ojavac(&self, args: &[String]) -> Result<(), ExecError> {
+        // ojavac passes the classpath to javac through a system property,
+        // which is global to the JVM, so each JVM only runs one compilation
+        // at a time. Taking it out of the pool takes care of that.
+        self.with_jvm(&[self.compiler_jar()?], |jvm| {
+            let mut cmd = JvmCommand::new(jvm, MAIN_CLASS);
+            cmd.args(args).stdout(Stdio::Piped).stderr(Stdio::Piped);
+            let mut task = cmd.spawn()?;
+
+            forward_output(&mut task)?;
+            task.wait()?;
+
+            Ok(())
+        })
+    }
+
+    /// Stops all JVMs in the pool, and removes ojavac's jar if it was
+    /// extracted. Tasks that run after this start new JVMs.
+    pub fn shutdown(&self) {
+        let jvms: Vec<_> = match self.jvms.lock() {
+            Ok(mut jvms) => jvms.drain().flat_map(|(_, el)| el).collect(),
+            Err(e) => e.into_inner().drain().flat_map(|(_, el)| el).collect(),
+        };
+
+        jvms.into_iter().for_each(stop);
+
+        match self.compiler_jar.lock() {
+            Ok(mut jar) => drop(jar.take()),
+            Err(e) => drop(e.into_inner().take()),
+        }
+    }
+
+    fn jvms(&self) -> Result<MutexGuard<'_, Jvms>, ExecError> {
+        self.jvms.lock().map_err(|_| poisoned())
+    }
+
+    /// Returns the path of ojavac's jar, extracting it if necessary.
+    fn compiler_jar(&self) -> Result<PathBuf, ExecError> {
+        let mut jar = self.compiler_jar.lock().map_err(|_| poisoned())?;
+        let jar = jar.get_or_insert_with(JarFile::get);
+
+        // safety check so that you don't just get a class not found error from
+        // the dispatcher that doesn't really say anything
+        if !jar.exists() {
+            return Err(ExecError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("could not locate jar file '{}'", jar.display()),
+            )));
+        }
+
+        Ok(jar.to_path_buf())
+    }
+}
+
+/// Stops the JVMs of the build, see [`JvmPool::shutdown()`].
+pub fn shutdown(projects: &[Project]) {
+    let pool = projects
+        .first()
+        .and_then(|el| el.extensions().get::<Arc<JvmPool>>());
+
+    if let Some(pool) = pool {
+        pool.shutdown();
+    }
+}
+
+fn stop(jvm: DirectJvm) {
+    if let Err(e) = jvm.shutdown() {
+        warn!("failed to stop JVM: {}", e);
+    }
+}
+
+fn poisoned() -> ExecError {
+    ExecError::Io(io::Error::new(
+        io::ErrorKind::Other,
+        "a task panicked while using the JVM pool",
+    ))
+}
+
+/// Copies the piped standard output and error streams of `task` to our own
+/// until the task closes them. The dispatcher only buffers a few kilobytes of
+/// each stream, and the task blocks once one of them is full, so they get read
+/// at the same time.
+pub fn forward_output<T>(task: &mut T) -> io::Result<()>
+where
+    T: JvmTask,
+    T::Stderr: Send,
+{
+    let stdout = task.stdout().take();
+    let stderr = task.stderr().take();
+
+    thread::scope(|s| {
+        let stderr =
+            stderr.map(|mut stderr| s.spawn(move || io::copy(&mut stderr, &mut io::stderr())));
+
+        if let Some(mut stdout) = stdout {
+            io::copy(&mut stdout, &mut io::stdout())?;
+        }
+
+        if let Some(handle) = stderr {
+            handle.join().unwrap()?;
+        }
+
+        Ok(())
+    })
+}
+
+enum JarFile {
+    #[cfg(install)]
+    Installed(std::path::PathBuf),
+    #[cfg(not(install))]
+    Temp(tempfile::TempPath),
+}
+
+impl Deref for JarFile {
+    type Target = Path;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            #[cfg(install)]
+            JarFile::Installed(v) => &v,
+            #[cfg(not(install))]
+            JarFile::Temp(v) => v,
+        }
+    }
+}
+
+impl JarFile {
+    #[cfg(not(install))]
+    fn get() -> Self {
+        use std::io::Write as _;
+
+        let jar = include_bytes!("../../../java-compilers/ojavac/java/ojavac.jar");
+        let mut tf = tempfile::NamedTempFile::new().expect("failed to create temporary file");
+        tf.write_all(jar).expect("failed to write jar contents");
+        JarFile::Temp(tf.into_temp_path())
+    }
+
+    #[cfg(install)]
+    fn get() -> Self {
+        let exec_dir = Path::new(origami_common::LIBEXECDIR);
+        let mut path = if exec_dir.is_relative() {
+            let mut path = std::env::current_exe().unwrap_or_default();
+            path.pop();
+            path.push(exec_dir);
+            path
+        } else {
+            exec_dir.to_path_buf()
+        };
+
+        path.push("ojavac.jar");
+        JarFile::Installed(path)
+    }
+}
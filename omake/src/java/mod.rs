@@ -1,58 +1,91 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
 use std::fs::File;
 use std::io::Write;
 use std::ops::{Generator, GeneratorState};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::Arc;
 use std::{fs, io};
 
-use crate::java::plugin::{ClassPathEntry, JavaExtension};
-use crate::task::{input_changed, input_changed_multi, Context, PathDep, Task};
+use crate::java::plugin::{ClassPathEntry, JavaExtension, JavaSourceSet};
+use crate::task::{
+    hash_file, input_changed_multi, omake_dir, Context, PathDep, StaleReason, Task, TaskError,
+    MAX_ROUNDS,
+};
 
+mod abi;
+pub mod jar;
+mod junit;
+pub mod jvm;
 pub mod plugin;
+pub mod run;
+pub mod test;
 
 #[derive(Debug)]
 pub struct JavaTask {
+    set: Arc<JavaSourceSet>,
     input_root: PathBuf,
-    output_root: PathBuf,
     class: PathBuf,
 }
 
 impl Task for JavaTask {
-    fn make(&self, ctx: &Context) -> Result<(), String> {
+    fn name(&self) -> String {
+        format!("{}:{}", self.set.task_name(), self.class.to_string_lossy())
+    }
+
+    fn make(&self, ctx: &Context) -> Result<(), TaskError> {
         let c = ctx.get_extension::<JavaExtension>().unwrap();
         // let temp_dir = ctx.create_temp_dir();
         let input_file = self.input_file();
-        c.exec_javac(&input_file, &self.output_root, &self.manifest_path()).unwrap();
+        c.exec_javac(
+            ctx,
+            &self.set,
+            &[input_file],
+            self.output_root(),
+            &self.manifest_path(),
+        )?;
+        self.write_abi()?;
 
         Ok(())
     }
 
     fn inputs(&self, ctx: &Context) -> Vec<PathDep> {
         let mut vec = Vec::with_capacity(1);
-        let c = ctx.get_extension::<JavaExtension>().unwrap();
 
         vec.push(PathDep::new(self.input_file()));
 
-        let manifest = self.manifest_path();
-        let manifest_outdated = input_changed(&self.input_file(), &manifest).unwrap();
-
-        if manifest_outdated {
-            // We don't know what classes this class uses, so we'll just assume
-            // it needs every other dependency on the classpath compiled
-            // This will break for tasks that generate java files, but let's fix
-            // that later.
-            vec.extend(c.class_path().iter().map(|el| match el {
-                ClassPathEntry::File(path) => PathDep::new(path.clone()),
-                ClassPathEntry::Dir(path) => PathDep::new(path.clone()).with_dir(true, "*.class"),
-            }));
+        let manifest_content = if self.manifest_trusted(ctx, &vec) {
+            fs::read_to_string(self.manifest_path()).ok()
         } else {
-            let manifest_content = std::fs::read_to_string(&manifest).unwrap();
-            vec.extend(
-                manifest_content
-                    .lines()
-                    .filter_map(|el| el.strip_prefix("<- "))
-                    .map(|el| PathBuf::from(el).into()),
-            );
+            None
+        };
+
+        match manifest_content {
+            None => {
+                // We don't know what classes this class uses, so we'll just assume
+                // it needs every other dependency on the classpath compiled
+                // This will break for tasks that generate java files, but let's fix
+                // that later.
+                vec.extend(self.set.class_path().iter().map(|el| match el {
+                    ClassPathEntry::File(path) => PathDep::new(path.clone()),
+                    ClassPathEntry::Dir(path) => PathDep::new(path.clone()).with_dir(true, "*.class"),
+                }));
+            }
+            Some(manifest_content) => {
+                // Other classes are only needed for their ABI, which is part
+                // of the fingerprint instead, so that changing them doesn't
+                // cause a recompile unless their ABI changed.
+                vec.extend(
+                    manifest_content
+                        .lines()
+                        .filter_map(|el| el.strip_prefix("<- "))
+                        .map(PathBuf::from)
+                        .filter(|el| !is_class_dependency(el))
+                        .map(PathDep::from),
+                );
+            }
         }
 
         vec
@@ -62,53 +95,153 @@ impl Task for JavaTask {
         let mut vec = Vec::with_capacity(2);
         let outfile = self.manifest_path();
 
-        let manifest_outdated = input_changed_multi(&self.inputs(ctx), &[&outfile]).unwrap();
-
-        if manifest_outdated {
-            // We don't know what this class outputs, especially if we never had
-            // an outfile. You might think that there's an 1:1 mapping from
-            // X.java to X.class, but that's not necessarily the case. Compiling
-            // the file might yield more than just X.class, and X.class might
-            // not even be generated, since the class file inside the source
-            // does not need to equal the file name if the class is not public.
-            // So here, we just say this task outputs _any_ file in the output
-            // root.
-            vec.push(PathDep::new(self.output_root.clone()).with_dir(true, "*.class"));
+        let outfile_content = if self.manifest_trusted(ctx, &self.inputs(ctx)) {
+            fs::read_to_string(&outfile).ok()
         } else {
-            // Since this task isn't out of date, we know exactly what files
-            // it outputs, namely those recorded in the manifest, one on each
-            // line.
-            let outfile_content = std::fs::read_to_string(&outfile).unwrap();
+            None
+        };
+
+        match outfile_content {
+            None => {
+                // We don't know what this class outputs, especially if we never had
+                // an outfile. You might think that there's an 1:1 mapping from
+                // X.java to X.class, but that's not necessarily the case. Compiling
+                // the file might yield more than just X.class, and X.class might
+                // not even be generated, since the class file inside the source
+                // does not need to equal the file name if the class is not public.
+                // So here, we just say this task outputs _any_ file in the output
+                // root.
+                vec.push(PathDep::new(self.output_root().to_path_buf()).with_dir(true, "*.class"));
+            }
+            Some(outfile_content) => {
+                // Since this task isn't out of date, we know exactly what files
+                // it outputs, namely those recorded in the manifest, one on each
+                // line.
+                vec.extend(
+                    outfile_content
+                        .lines()
+                        .filter_map(|el| el.strip_prefix("-> "))
+                        .map(|el| PathBuf::from(el).into()),
+                );
+            }
+        }
+
+        vec.push(self.abi_path().into());
+
+        // insert the outfile at position 0 to mark it as the "main" output.
+        vec.insert(0, outfile.into());
+
+        vec
+    }
+
+    fn needs_exec(&self, ctx: &Context) -> Option<StaleReason> {
+        let manifest = self.manifest_path();
+
+        let reason = input_changed_multi(&self.inputs(ctx), &self.outputs(ctx))
+            .unwrap_or_else(|e| Some(e.into()))?;
+
+        // the manifest is the main output, so say so if it's the culprit
+        Some(match reason {
+            StaleReason::OutputMissing(path) if path == manifest => {
+                StaleReason::ManifestMissing(path)
+            }
+            StaleReason::InputNewer { output, .. } if output == manifest => {
+                StaleReason::ManifestOutdated(output)
+            }
+            reason => reason,
+        })
+    }
+
+    fn soft_inputs(&self, _ctx: &Context) -> Vec<PathDep> {
+        // Classes from other directories on the class path aren't inputs
+        // once the manifest is known, since only their ABI matters, but they
+        // still have to be compiled first.
+        self.set
+            .class_path()
+            .iter()
+            .filter_map(|el| match el {
+                ClassPathEntry::File(_) => None,
+                ClassPathEntry::Dir(path) => Some(PathDep::new(path.clone()).with_dir(true, "*.class")),
+            })
+            .collect()
+    }
+
+    fn fingerprint(&self, ctx: &Context) -> Option<String> {
+        let c = ctx.get_extension::<JavaExtension>().unwrap();
+        let mut s = c.javac_fingerprint(
+            &self.set,
+            &[self.input_file()],
+            self.output_root(),
+            &self.manifest_path(),
+        );
+        let input_file = self.input_file();
+
+        let manifest_content = fs::read_to_string(self.manifest_path()).unwrap_or_default();
+        let dependencies = manifest_content
+            .lines()
+            .filter_map(|el| el.strip_prefix("<- "))
+            .map(Path::new)
+            .filter(|el| is_class_dependency(el) && *el != input_file);
+
+        for dependency in dependencies {
+            let hash = self.dependency_abi(dependency);
+            s.push_str(&format!("\n{} {}", dependency.display(), hash.as_deref().unwrap_or("missing")));
+        }
+
+        Some(s)
+    }
+
+    fn commands(&self, ctx: &Context) -> Vec<String> {
+        let c = ctx.get_extension::<JavaExtension>().unwrap();
+        vec![c.javac_command_line(
+            &self.set,
+            &[self.input_file()],
+            self.output_root(),
+            &self.manifest_path(),
+        )]
+    }
+
+    fn produced_files(&self, ctx: &Context) -> Vec<PathBuf> {
+        // Don't care whether the manifest is up to date here, the files in
+        // it are the ones we wrote last time.
+        let manifest = self.manifest_path();
+        let mut vec = vec![self.state_path(ctx), self.abi_path()];
+
+        if let Ok(content) = fs::read_to_string(&manifest) {
             vec.extend(
-                outfile_content
+                content
                     .lines()
                     .filter_map(|el| el.strip_prefix("-> "))
-                    .map(|el| PathBuf::from(el).into()),
+                    .map(PathBuf::from),
             );
         }
 
-        // insert the outfile at position 0 to mark it as the "main" output.
-        vec.insert(0, outfile.into());
-
+        vec.push(manifest);
         vec
     }
 
-    fn needs_exec(&self, ctx: &Context) -> bool {
-        input_changed_multi(&self.inputs(ctx), &self.outputs(ctx)).unwrap_or(true)
+    fn state_path(&self, _ctx: &Context) -> PathBuf {
+        let mut path = self.manifest_path();
+        path.set_extension("ostate");
+        path
     }
 }
 
 impl JavaTask {
-    fn new(input_root: PathBuf, output_root: PathBuf, class: PathBuf) -> Self {
+    fn new(set: Arc<JavaSourceSet>, input_root: PathBuf, class: PathBuf) -> Self {
         JavaTask {
+            set,
             input_root,
-            output_root,
             class,
         }
     }
 
+    fn output_root(&self) -> &Path {
+        self.set.output_dir()
+    }
+
     fn manifest_path(&self) -> PathBuf {
-        let mut path = self.output_root.join(&self.class);
+        let mut path = self.output_root().join(&self.class);
         path.set_extension("omf");
         path
     }
@@ -119,13 +252,102 @@ impl JavaTask {
         path
     }
 
+    /// Returns the path of the file the hash of the ABI of the classes
+    /// compiled from this source file is stored in.
+    fn abi_path(&self) -> PathBuf {
+        let mut path = self.output_root().join(&self.class);
+        path.set_extension("abi");
+        path
+    }
+
+    /// Updates the ABI hash from the class files listed in the manifest. The
+    /// file only gets written if the hash changed, so tasks depending on it
+    /// don't see a change otherwise.
+    fn write_abi(&self) -> io::Result<()> {
+        let manifest_content = fs::read_to_string(self.manifest_path())?;
+        let class_files: Vec<_> = manifest_content
+            .lines()
+            .filter_map(|el| el.strip_prefix("-> "))
+            .filter(|el| el.ends_with(".class"))
+            .collect();
+
+        let hash = abi::abi_hash(&class_files)?;
+        let abi_path = self.abi_path();
+
+        if fs::read_to_string(&abi_path).ok().as_deref() != Some(hash.as_str()) {
+            fs::write(&abi_path, hash)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the ABI hash of a source or class file this class depends on.
+    /// For source files compiled by another task, that's the hash the task
+    /// recorded, or `pending` if it hasn't run yet. Hashing the source file
+    /// instead would make the fingerprint change once the task did, even if
+    /// this class compiled against the same ABI. Files where neither is
+    /// possible are hashed in full.
+    fn dependency_abi(&self, path: &Path) -> Option<String> {
+        let rel = self
+            .set
+            .input_dirs()
+            .iter()
+            .find_map(|el| path.strip_prefix(el).ok().map(Path::to_path_buf));
+
+        match rel {
+            Some(rel) if path.extension() == Some(OsStr::new("java")) => {
+                let mut abi_path = self.output_root().join(rel);
+                abi_path.set_extension("abi");
+                Some(fs::read_to_string(abi_path).unwrap_or_else(|_| "pending".to_string()))
+            }
+            _ if path.extension() == Some(OsStr::new("class")) => abi::abi_hash(&[path])
+                .ok()
+                .or_else(|| hash_file(path).ok()),
+            _ => hash_file(path).ok(),
+        }
+    }
+
+    /// Returns whether the manifest was written for the current contents of
+    /// `inputs`, so that the files listed in it can be used as this task's
+    /// dependencies.
+    fn manifest_trusted(&self, ctx: &Context, inputs: &[PathDep]) -> bool {
+        let manifest = self.manifest_path();
+
+        if let Ok(None) = input_changed_multi(inputs, &[&manifest]) {
+            return true;
+        }
+
+        // The timestamps say otherwise, but they change for example on a
+        // checkout, so check whether the contents did too.
+        let state = match ctx.load_state(self) {
+            None => return false,
+            Some(state) => state,
+        };
+
+        let paths: Vec<_> = inputs
+            .iter()
+            .flat_map(|el| el.paths().map(Cow::into_owned).collect::<Vec<_>>())
+            .chain(Some(manifest))
+            .collect();
+
+        state
+            .unchanged(paths.iter().map(PathBuf::as_path))
+            .unwrap_or(false)
+    }
+
     fn write_manifest(&self, dependencies: &[&str], outputs: &[&Path]) -> io::Result<()> {
-        let mut file = File::create(self.manifest_path())?;
+        let manifest_path = self.manifest_path();
+
+        if let Some(manifest_dir) = manifest_path.parent() {
+            fs::create_dir_all(manifest_dir)?;
+        }
+
+        let mut file = File::create(manifest_path)?;
         writeln!(file, "; Origami Manifest")?;
         writeln!(
             file,
             "; Autogenerated file for class '{}'.",
-            self.class.to_str().unwrap()
+            self.class.to_string_lossy()
         )?;
 
         writeln!(file, "; Do not edit.")?;
@@ -144,7 +366,7 @@ impl JavaTask {
             writeln!(file, "; Outputs")?;
 
             for output in outputs {
-                writeln!(file, "-> {}", output.to_str().unwrap())?;
+                writeln!(file, "-> {}", output.to_string_lossy())?;
             }
         }
 
@@ -152,6 +374,184 @@ impl JavaTask {
     }
 }
 
+/// Compiles all out of date source files of a source set in a single compiler
+/// invocation.
+#[derive(Debug)]
+pub struct JavaBatchTask {
+    set: Arc<JavaSourceSet>,
+    tasks: Vec<JavaTask>,
+}
+
+impl Task for JavaBatchTask {
+    fn name(&self) -> String {
+        self.set.task_name().to_string()
+    }
+
+    fn make(&self, ctx: &Context) -> Result<(), TaskError> {
+        // Compiling a class can change its ABI, which makes the classes
+        // depending on it out of date, so keep going until there are none.
+        for _ in 0..MAX_ROUNDS {
+            let stale: Vec<_> = self.tasks.iter().filter(|el| ctx.is_stale(*el)).collect();
+
+            if stale.is_empty() {
+                break;
+            }
+
+            self.compile(ctx, &stale)?;
+        }
+
+        Ok(())
+    }
+
+    fn inputs(&self, ctx: &Context) -> Vec<PathDep> {
+        self.tasks.iter().flat_map(|el| el.inputs(ctx)).collect()
+    }
+
+    fn outputs(&self, ctx: &Context) -> Vec<PathDep> {
+        self.tasks.iter().flat_map(|el| el.outputs(ctx)).collect()
+    }
+
+    fn needs_exec(&self, ctx: &Context) -> Option<StaleReason> {
+        self.tasks.iter().find_map(|el| el.needs_exec(ctx))
+    }
+
+    fn soft_inputs(&self, ctx: &Context) -> Vec<PathDep> {
+        // the same for all tasks of a set
+        self.tasks
+            .first()
+            .map_or_else(Vec::new, |el| el.soft_inputs(ctx))
+    }
+
+    fn fingerprint(&self, ctx: &Context) -> Option<String> {
+        let vec: Vec<_> = self.tasks.iter().filter_map(|el| el.fingerprint(ctx)).collect();
+        Some(vec.join("\n"))
+    }
+
+    fn produced_files(&self, ctx: &Context) -> Vec<PathBuf> {
+        self.tasks.iter().flat_map(|el| el.produced_files(ctx)).collect()
+    }
+
+    fn commands(&self, ctx: &Context) -> Vec<String> {
+        let c = ctx.get_extension::<JavaExtension>().unwrap();
+        let mut stale: Vec<_> = self.tasks.iter().filter(|el| ctx.is_stale(*el)).collect();
+
+        // Only a task this one depends on would run, which classes that makes
+        // out of date isn't known until it did.
+        if stale.is_empty() {
+            stale = self.tasks.iter().collect();
+        }
+
+        // the manifest is written to a temporary directory that doesn't exist
+        // yet
+        let temp_dir = omake_dir(ctx.project().build_root()).join("tmp").join("<temp>");
+        let manifest_path = temp_dir.join("batch.omf");
+        let input_files: Vec<_> = stale.iter().map(|el| el.input_file()).collect();
+        vec![c.javac_command_line(&self.set, &input_files, self.set.output_dir(), &manifest_path)]
+    }
+}
+
+impl JavaBatchTask {
+    fn new(set: Arc<JavaSourceSet>, tasks: Vec<JavaTask>) -> Self {
+        JavaBatchTask { set, tasks }
+    }
+
+    /// Compiles the source files of `stale` in one compiler invocation.
+    fn compile(&self, ctx: &Context, stale: &[&JavaTask]) -> Result<(), TaskError> {
+        let c = ctx.get_extension::<JavaExtension>().unwrap();
+
+        let temp_dir = ctx.create_temp_dir()?;
+        let batch_manifest_path = temp_dir.join("batch.omf");
+        let input_files: Vec<_> = stale.iter().map(|el| el.input_file()).collect();
+        c.exec_javac(
+            ctx,
+            &self.set,
+            &input_files,
+            self.set.output_dir(),
+            &batch_manifest_path,
+        )?;
+
+        // The compiler only writes a single manifest for all the classes, so
+        // split it up into the per-class manifests the single compilation
+        // tasks use. We can't tell which of the inputs each class actually
+        // needed, so every class gets all of them. The compiler only lists
+        // the files it looked up itself, not the ones it was asked to
+        // compile, but the classes can depend on each other just the same.
+        let batch_manifest = fs::read_to_string(&batch_manifest_path)?;
+        let input_names: Vec<_> = input_files.iter().map(|el| el.to_string_lossy()).collect();
+        let dependencies: Vec<_> = batch_manifest
+            .lines()
+            .filter_map(|el| el.strip_prefix("<- "))
+            .chain(input_names.iter().map(|el| &**el))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let mut outputs = vec![Vec::new(); stale.len()];
+
+        for output in batch_manifest.lines().filter_map(|el| el.strip_prefix("-> ")) {
+            let output = Path::new(output);
+
+            for idx in self.output_owners(stale, output) {
+                outputs[idx].push(output);
+            }
+        }
+
+        for (task, outputs) in stale.iter().zip(outputs) {
+            task.write_manifest(&dependencies, &outputs)?;
+            task.write_abi()?;
+        }
+
+        // Only record the classes we compiled. The others might be out of
+        // date now because of ABI changes, and get compiled in the next
+        // round. The fingerprint of each class contains the ABI of the
+        // others, so this has to wait until all of them are written.
+        for task in stale {
+            ctx.record_state(*task)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the indices of the tasks in `tasks` that `output` belongs to.
+    /// `a/B.class` and `a/B$C.class` belong to the class `a/B`. Other class
+    /// files can come from any non-public class, so they are assigned to all
+    /// classes in the same package, or to every class if there are none.
+    fn output_owners(&self, tasks: &[&JavaTask], output: &Path) -> Vec<usize> {
+        let class = match output.strip_prefix(self.set.output_dir()) {
+            Ok(rel) => {
+                let mut class = rel.to_path_buf();
+                let file_name = rel.file_stem().and_then(|el| el.to_str()).unwrap_or("");
+                class.set_file_name(file_name.split('$').next().unwrap());
+                class
+            }
+            Err(_) => return (0..tasks.len()).collect(),
+        };
+
+        let same_class: Vec<_> = (0..tasks.len())
+            .filter(|&idx| tasks[idx].class == class)
+            .collect();
+
+        if !same_class.is_empty() {
+            return same_class;
+        }
+
+        let same_package: Vec<_> = (0..tasks.len())
+            .filter(|&idx| tasks[idx].class.parent() == class.parent())
+            .collect();
+
+        if !same_package.is_empty() {
+            return same_package;
+        }
+
+        (0..tasks.len()).collect()
+    }
+}
+
+/// Returns whether `path`, listed as a dependency in a manifest, is another
+/// class, either as source or compiled.
+fn is_class_dependency(path: &Path) -> bool {
+    matches!(path.extension().and_then(OsStr::to_str), Some("java" | "class"))
+}
+
 fn find_files<'a, P: AsRef<Path> + 'a>(
     path: P,
 ) -> FindFiles<impl Generator<Yield = io::Result<PathBuf>, Return = ()>> {
@@ -222,3 +622,36 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::testutil::{build, write};
+
+    #[test]
+    fn batch_recompiles_dependents_on_abi_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let build_root = dir.path().join("build");
+        let a = dir.path().join("src/p/A.java");
+        let b = dir.path().join("src/p/B.java");
+
+        write(&build_root.join("obuildroot"), "p\n");
+        write(
+            &build_root.join("p/obuild.toml"),
+            "[project]\nname = \"p\"\nsource_root = \"../..\"\n\n[java]\nbatch = true\n",
+        );
+        write(&a, "package p;\npublic class A { long f() { return new B().g(); } }\n");
+        write(&b, "package p;\npublic class B { public int g() { return 1; } }\n");
+
+        assert!(build(&build_root));
+        let a_class = build_root.join("p/src/p/A.class");
+        let before = fs::read(&a_class).unwrap();
+
+        // A still compiles, but calls a method with a different descriptor
+        write(&b, "package p;\npublic class B { public long g() { return 1; } }\n");
+
+        assert!(build(&build_root));
+        assert!(fs::read(&a_class).unwrap() != before, "A wasn't recompiled");
+    }
+}
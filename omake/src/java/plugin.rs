@@ -1,42 +1,192 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::BufReader;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
-use std::{fs, io};
+use std::process::Command;
+use std::sync::Arc;
+use std::{env, fs, io};
 
+use jvmapi::javacli;
+use origami_common::lockfile::{RemoteFile, WorkspaceLock};
 use serde::Deserialize;
-use thiserror::Error;
 
-use crate::java::JavaTask;
+use crate::java::jar::JarTask;
+use crate::java::jvm::{self, JvmPool};
+use crate::java::run::{Launch, RunSettings};
+use crate::java::test::{TestSettings, TestTask};
+use crate::java::{JavaBatchTask, JavaTask};
 use crate::plugin::Plugin;
 use crate::project::Project;
-use crate::task::Task;
-use std::process::{ExitStatus, Command};
+use crate::resources::plugin::ResourcesExtension;
+use crate::sourceset;
+use crate::task::{Context, ExecError, PathDep, Task};
+use crate::{Error, Result};
 
-pub fn register(project: &mut Project) {
+/// The name of the lockfile oresolve writes into the project's source root.
+const LOCKFILE_NAME: &str = "origami.lock";
+
+/// The directory oresolve downloads dependencies into, relative to the
+/// project's source root.
+const DISTFILES_DIR: &str = "origami/distfiles";
+
+/// The environment variable to override the path of the ojavac executable
+/// with.
+const OJAVAC_ENV: &str = "OMAKE_OJAVAC";
+
+/// Registers the Java plugin for `project`. `dependencies` are the projects
+/// it depends on, which need to have the plugin registered already.
+pub fn register(project: &mut Project, dependencies: &[&Project]) -> Result<()> {
     #[derive(Deserialize)]
     struct BuildSettings {
         #[serde(default)]
         java: PluginSettings,
+        #[serde(default)]
+        test: TestSettings,
+        #[serde(default)]
+        run: RunSettings,
     }
 
-    #[derive(Default, Deserialize)]
+    // `sources` and `output` are read as part of the main source set
+    #[derive(Deserialize)]
     struct PluginSettings {
-        sources: Option<Vec<PathBuf>>,
-        output: Option<PathBuf>,
+        #[serde(default)]
+        batch: bool,
+        #[serde(default = "default_direct_jvm")]
+        direct_jvm: bool,
+        main_class: Option<String>,
+        ojavac: Option<PathBuf>,
+    }
+
+    impl Default for PluginSettings {
+        fn default() -> Self {
+            PluginSettings {
+                batch: false,
+                direct_jvm: default_direct_jvm(),
+                main_class: None,
+                ojavac: None,
+            }
+        }
+    }
+
+    fn default_direct_jvm() -> bool {
+        true
     }
 
     let BuildSettings {
-        java: PluginSettings { sources, output },
-    } = BuildSettings::deserialize(project.build_file().clone()).unwrap();
+        java: PluginSettings {
+            batch,
+            direct_jvm,
+            main_class,
+            ojavac,
+        },
+        test,
+        run,
+    } = BuildSettings::deserialize(project.build_file().clone())?;
+
+    let javac_override =
+        configured_javac_path(project.build_root(), ojavac, env::var_os(OJAVAC_ENV));
+
+    // The shared JVM always runs the bundled compiler, so one configured
+    // explicitly has to run as its own process.
+    let direct_jvm = direct_jvm && javac_override.is_none();
+    let javac_path = javac_override.unwrap_or_else(default_javac_path);
+
+    let lock_dependencies = read_lock_dependencies(project.source_root())?;
+    let mut class_path: Vec<_> = lock_dependencies
+        .iter()
+        .map(|el| ClassPathEntry::File(el.path.clone()))
+        .collect();
+
+    // Classes of projects we depend on and everything on their classpath.
+    for dependency in dependencies {
+        if let Some(ext) = dependency.extensions().get::<JavaExtension>() {
+            let entries = Some(ClassPathEntry::Dir(ext.data.main_set().output_dir.clone()))
+                .into_iter()
+                .chain(ext.class_path());
+
+            for entry in entries {
+                push_unique(&mut class_path, entry);
+            }
+        }
+    }
 
-    let inputs = sources.unwrap_or_else(|| vec![PathBuf::from("src")]);
-    let output_root = output.unwrap_or_else(|| PathBuf::from("."));
+    let mut sets: Vec<Arc<JavaSourceSet>> = Vec::new();
+
+    for set in sourceset::read(project)? {
+        // Other sets see the classes of the main set, and everything they
+        // can see.
+        let mut set_class_path = match sets.first() {
+            None => class_path.clone(),
+            Some(main) => Some(ClassPathEntry::Dir(main.output_dir.clone()))
+                .into_iter()
+                .chain(main.class_path.iter().cloned())
+                .collect(),
+        };
+
+        for entry in set.class_path() {
+            let path = project.source_root().join(entry);
+
+            let entry = if path.is_dir() {
+                ClassPathEntry::Dir(path)
+            } else {
+                ClassPathEntry::File(path)
+            };
 
-    let d = Rc::new(JavaData {
-        source_root: project.source_root().to_path_buf(),
+            push_unique(&mut set_class_path, entry);
+        }
+
+        sets.push(Arc::new(JavaSourceSet {
+            name: set.name().to_string(),
+            task_name: set.task_name("compile", "Java"),
+            input_dirs: set
+                .sources()
+                .iter()
+                .map(|el| project.source_root().join(el))
+                .collect(),
+            output_dir: project.build_root().join(set.output()),
+            class_path: set_class_path,
+        }));
+    }
+
+    // What the main set needs at runtime: its own classes and resources,
+    // everything the projects it depends on need, and its class path.
+    let mut runtime_class_path = vec![ClassPathEntry::Dir(sets[0].output_dir.clone())];
+
+    let resources = project
+        .extensions()
+        .get::<ResourcesExtension>()
+        .and_then(|el| el.output_dir(sourceset::MAIN));
+
+    if let Some(resources) = resources {
+        runtime_class_path.push(ClassPathEntry::Dir(resources));
+    }
+
+    for dependency in dependencies {
+        if let Some(ext) = dependency.extensions().get::<JavaExtension>() {
+            for entry in ext.runtime_class_path() {
+                push_unique(&mut runtime_class_path, entry.clone());
+            }
+        }
+    }
+
+    for entry in &sets[0].class_path {
+        push_unique(&mut runtime_class_path, entry.clone());
+    }
+
+    let d = Arc::new(JavaData {
+        name: project.name().to_string(),
         build_root: project.build_root().to_path_buf(),
-        inputs,
-        output_root,
-        javac_path: PathBuf::from("/home/saiko/src/origami/target/debug/ojavac"),
+        sets,
+        runtime_class_path,
+        batch,
+        direct_jvm,
+        main_class,
+        javac_path,
+        lock_dependencies,
+        test,
+        run,
     });
 
     let plugin = JavaPlugin { data: d.clone() };
@@ -44,56 +194,348 @@ pub fn register(project: &mut Project) {
 
     project.extensions_mut().insert(ext);
     project.register_plugin(plugin);
+
+    Ok(())
+}
+
+fn push_unique(class_path: &mut Vec<ClassPathEntry>, entry: ClassPathEntry) {
+    if !class_path.contains(&entry) {
+        class_path.push(entry);
+    }
+}
+
+/// Returns the ojavac executable configured for the project: the one set in
+/// its build file, relative to its build root, or else the one from the
+/// environment variable [`OJAVAC_ENV`].
+fn configured_javac_path(
+    build_root: &Path,
+    build_file: Option<PathBuf>,
+    env: Option<OsString>,
+) -> Option<PathBuf> {
+    build_file
+        .map(|path| build_root.join(path))
+        .or_else(|| env.map(PathBuf::from))
+}
+
+/// Returns the path of the ojavac executable to use if none is configured.
+/// When not installed, ojavac gets built into the same cargo target directory
+/// as omake.
+#[cfg(not(install))]
+fn default_javac_path() -> PathBuf {
+    let mut path = env::current_exe().unwrap_or_default();
+    path.set_file_name(format!("ojavac{}", env::consts::EXE_SUFFIX));
+    path
+}
+
+/// Returns the path of the ojavac executable to use if none is configured.
+#[cfg(install)]
+fn default_javac_path() -> PathBuf {
+    let exec_dir = Path::new(origami_common::LIBEXECDIR);
+    let mut path = if exec_dir.is_relative() {
+        let mut path = env::current_exe().unwrap_or_default();
+        path.pop();
+        path.push(exec_dir);
+        path
+    } else {
+        exec_dir.to_path_buf()
+    };
+
+    path.push(format!("ojavac{}", env::consts::EXE_SUFFIX));
+    path
+}
+
+/// Returns the JAR files of all the dependencies recorded in the project's
+/// lockfile, as written by oresolve. Projects without a lockfile don't have
+/// any dependencies. Whether they have been downloaded yet is only checked
+/// when compiling, so that the project can be cleaned or inspected without
+/// them.
+fn read_lock_dependencies(source_root: &Path) -> Result<Vec<LockDependency>> {
+    let lock_path = source_root.join(LOCKFILE_NAME);
+
+    let file = match File::open(&lock_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let lock = WorkspaceLock::read(BufReader::new(file))
+        .map_err(|e| Error::Lockfile(lock_path.clone(), e.to_string()))?;
+
+    let distfiles = source_root.join(DISTFILES_DIR);
+    let mut vec = Vec::new();
+
+    for package in &lock.packages {
+        let jars = package
+            .sources
+            .iter()
+            .filter_map(RemoteFile::file_name)
+            .filter(|el| el.ends_with(".jar"));
+
+        for jar in jars {
+            vec.push(LockDependency {
+                name: format!("{} {}", package.name, package.version),
+                path: distfiles.join(jar),
+            });
+        }
+    }
+
+    Ok(vec)
+}
+
+/// A JAR file of a dependency from the lockfile.
+#[derive(Debug)]
+struct LockDependency {
+    /// The name and version of the package the file belongs to.
+    name: String,
+    /// Where oresolve downloads the file to.
+    path: PathBuf,
 }
 
 #[derive(Debug)]
 pub struct JavaData {
-    source_root: PathBuf,
+    name: String,
     build_root: PathBuf,
-    inputs: Vec<PathBuf>,
-    output_root: PathBuf,
+    /// The source sets, the main one first.
+    sets: Vec<Arc<JavaSourceSet>>,
+    /// The class path to run the main set's classes with.
+    runtime_class_path: Vec<ClassPathEntry>,
+    /// Whether to compile all out of date source files in one compiler
+    /// invocation instead of one at a time.
+    batch: bool,
+    /// Whether to run the compiler in a JVM shared by the whole build instead
+    /// of starting a new ojavac process for every invocation. This is off
+    /// when the ojavac executable is configured explicitly.
+    direct_jvm: bool,
+    /// The class the jar's manifest points to as its entry point.
+    main_class: Option<String>,
     javac_path: PathBuf,
+    /// The dependencies from the lockfile, which need to be downloaded
+    /// before compiling.
+    lock_dependencies: Vec<LockDependency>,
+    test: TestSettings,
+    run: RunSettings,
+}
+
+impl JavaData {
+    fn main_set(&self) -> &Arc<JavaSourceSet> {
+        &self.sets[0]
+    }
+
+    /// Returns the directory the test reports of `set` get written to.
+    fn reports_dir(&self, set: &JavaSourceSet) -> PathBuf {
+        self.build_root.join("test-results").join(&set.name)
+    }
+
+    /// Returns the path of the jar containing the project's classes.
+    fn jar_path(&self) -> PathBuf {
+        self.build_root.join("libs").join(format!("{}.jar", self.name))
+    }
+}
+
+/// A source set with the paths resolved and the class path it gets compiled
+/// against.
+#[derive(Debug)]
+pub struct JavaSourceSet {
+    name: String,
+    /// The name of the task compiling this set, `compileJava` for the main
+    /// set.
+    task_name: String,
+    input_dirs: Vec<PathBuf>,
+    output_dir: PathBuf,
+    class_path: Vec<ClassPathEntry>,
+}
+
+impl JavaSourceSet {
+    pub fn task_name(&self) -> &str {
+        &self.task_name
+    }
+
+    /// Returns the directories source files are read from.
+    pub fn input_dirs(&self) -> &[PathBuf] {
+        &self.input_dirs
+    }
+
+    /// Returns the directory compiled classes get written to.
+    pub fn output_dir(&self) -> &Path {
+        &self.output_dir
+    }
+
+    pub fn class_path(&self) -> &[ClassPathEntry] {
+        &self.class_path
+    }
+
+    /// Returns whether any of the source directories contains the source
+    /// file of `class`.
+    fn has_source(&self, class: &Path) -> bool {
+        let mut file = class.to_path_buf();
+        file.set_extension("java");
+
+        self.input_dirs.iter().any(|el| el.join(&file).is_file())
+    }
 }
 
 pub struct JavaPlugin {
-    data: Rc<JavaData>,
+    data: Arc<JavaData>,
 }
 
 impl Plugin for JavaPlugin {
-    fn create_tasks(&self, project: &Project) -> Vec<Box<dyn Task>> {
+    fn create_tasks(&self, project: &Project) -> Result<Vec<Box<dyn Task>>> {
         let data = &self.data;
 
         let mut vec: Vec<Box<dyn Task>> = Vec::new();
-        let input_dir = project.source_root().join("src");
-        let output_dir = project.build_root().join("src");
 
-        let a = recursive_find_java(&input_dir).unwrap();
+        for set in &data.sets {
+            let tasks = create_compile_tasks(set)?;
 
-        for file in a {
-            let mut class = file.strip_prefix(&input_dir).unwrap().to_path_buf();
-            class.set_file_name(class.file_stem().unwrap().to_os_string());
-            vec.push(Box::new(JavaTask::new(
-                input_dir.clone(),
-                output_dir.clone(),
-                class,
+            if data.batch {
+                vec.push(Box::new(JavaBatchTask::new(set.clone(), tasks)));
+            } else {
+                vec.extend(tasks.into_iter().map(|el| Box::new(el) as Box<dyn Task>));
+            }
+        }
+
+        // only the main set goes into the jar
+        let main = data.main_set();
+        let mut jar_inputs = vec![PathDep::new(main.output_dir.clone()).with_dir(true, "*.class")];
+
+        let resources = project
+            .extensions()
+            .get::<ResourcesExtension>()
+            .and_then(|el| el.output_dir(&main.name));
+
+        if let Some(resources) = resources {
+            jar_inputs.push(PathDep::new(resources).with_dir(true, "*"));
+        }
+
+        vec.push(Box::new(JarTask::new(
+            jar_inputs,
+            data.jar_path(),
+            data.main_class.clone(),
+        )));
+
+        if let Some(set) = data.sets.iter().find(|el| el.name == sourceset::TEST) {
+            vec.push(Box::new(TestTask::new(
+                set.clone(),
+                runtime_class_path(project, set, &data.runtime_class_path),
+                data.reports_dir(set),
+                data.test.clone(),
             )));
         }
 
-        vec
+        Ok(vec)
+    }
+
+    fn remove_stale_outputs(&self, project: &Project) -> io::Result<()> {
+        let ctx = Context::new(project);
+
+        for set in &self.data.sets {
+            let manifests: Vec<_> = PathDep::new(set.output_dir.clone())
+                .with_dir(true, "*.omf")
+                .paths()
+                .map(Cow::into_owned)
+                .collect();
+
+            for manifest in manifests {
+                let mut class = manifest.strip_prefix(&set.output_dir).unwrap().to_path_buf();
+                class.set_extension("");
+
+                if set.has_source(&class) {
+                    continue;
+                }
+
+                println!("removing outputs of deleted class '{}'", class.display());
+
+                // the source directory doesn't matter for the outputs
+                let task = JavaTask::new(set.clone(), PathBuf::new(), class);
+
+                for path in task.produced_files(&ctx) {
+                    match fs::remove_file(&path) {
+                        Ok(_) => {}
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+
+        Ok(())
     }
 }
 
+/// Returns the class path `set` runs with: its own classes and resources,
+/// then what the main set runs with, then the class path it was compiled
+/// against.
+fn runtime_class_path(
+    project: &Project,
+    set: &JavaSourceSet,
+    main: &[ClassPathEntry],
+) -> Vec<ClassPathEntry> {
+    let mut class_path = vec![ClassPathEntry::Dir(set.output_dir.clone())];
+
+    let resources = project
+        .extensions()
+        .get::<ResourcesExtension>()
+        .and_then(|el| el.output_dir(&set.name));
+
+    if let Some(resources) = resources {
+        class_path.push(ClassPathEntry::Dir(resources));
+    }
+
+    for entry in main.iter().chain(&set.class_path) {
+        push_unique(&mut class_path, entry.clone());
+    }
+
+    class_path
+}
+
+/// Creates a task for every source file in `set`.
+fn create_compile_tasks(set: &Arc<JavaSourceSet>) -> Result<Vec<JavaTask>> {
+    // All source directories of a set get compiled into the same output
+    // directory, so the same class can't be in more than one of them.
+    let mut classes = BTreeMap::new();
+
+    for input_dir in &set.input_dirs {
+        for file in recursive_find_java(input_dir)? {
+            let mut class = file.strip_prefix(input_dir).unwrap().to_path_buf();
+            class.set_file_name(class.file_stem().unwrap().to_os_string());
+
+            let entry = (input_dir.clone(), file.clone());
+
+            if let Some((_, first)) = classes.insert(class.clone(), entry) {
+                return Err(Error::DuplicateClass {
+                    class: class.to_string_lossy().into_owned(),
+                    first,
+                    second: file,
+                });
+            }
+        }
+    }
+
+    Ok(classes
+        .into_iter()
+        .map(|(class, (input_dir, _))| JavaTask::new(set.clone(), input_dir, class))
+        .collect())
+}
+
+/// Returns all Java source files in `path` and its subdirectories, or none if
+/// it doesn't exist.
 fn recursive_find_java(path: &Path) -> io::Result<Vec<PathBuf>> {
     let mut vec = Vec::new();
 
-    for x in fs::read_dir(path)? {
+    let rd = match fs::read_dir(path) {
+        Ok(rd) => rd,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec),
+        Err(e) => return Err(e),
+    };
+
+    for x in rd {
         let x = x?;
 
         if x.file_type()?.is_dir() {
             vec.extend(recursive_find_java(&x.path())?.into_iter());
         } else {
             let file_name = x.file_name();
-            if file_name.to_str().unwrap().ends_with(".java") {
+            if file_name.to_string_lossy().ends_with(".java") {
                 vec.push(path.join(file_name));
             }
         }
@@ -103,69 +545,331 @@ fn recursive_find_java(path: &Path) -> io::Result<Vec<PathBuf>> {
 }
 
 pub struct JavaExtension {
-    data: Rc<JavaData>,
+    data: Arc<JavaData>,
 }
 
 impl JavaExtension {
-    pub fn exec_javac(&self, input: &Path, output_root: &Path, manifest_path: &Path) -> Result<(), ExecError> {
+    pub fn exec_javac<P>(
+        &self,
+        ctx: &Context,
+        set: &JavaSourceSet,
+        inputs: &[P],
+        output_root: &Path,
+        manifest_path: &Path,
+    ) -> Result<(), ExecError>
+    where
+        P: AsRef<Path>,
+    {
+        if let Some(dep) = self.data.lock_dependencies.iter().find(|el| !el.path.is_file()) {
+            return Err(ExecError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "dependency '{}' has not been downloaded to '{}', run oresolve first",
+                    dep.name,
+                    dep.path.display()
+                ),
+            )));
+        }
+
         if let Some(manifest_dir) = manifest_path.parent() {
             fs::create_dir_all(manifest_dir)?;
         }
 
-        let mut cmd = Command::new(&self.data.javac_path);
+        println!("{}", self.javac_command_line(set, inputs, output_root, manifest_path));
 
-        for entry in &self.data.inputs {
-            cmd.arg("--include");
-            cmd.arg(self.data.source_root.join(entry));
+        if self.data.direct_jvm {
+            // The compiler doesn't report an exit code when running in the
+            // shared JVM, but it only writes the manifest if it succeeds, so
+            // make sure there's no old one around.
+            match fs::remove_file(manifest_path) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            let jvms = ctx.get_extension::<Arc<JvmPool>>().unwrap();
+            jvms.run_ojavac(&self.ojavac_main_args(set, inputs, output_root, manifest_path))?;
+
+            if manifest_path.is_file() {
+                Ok(())
+            } else {
+                Err(ExecError::Failed)
+            }
+        } else {
+            let exit_code = Command::new(&self.data.javac_path)
+                .args(self.javac_args(set, inputs, output_root, manifest_path))
+                .status()
+                .map_err(|e| {
+                    let path = self.data.javac_path.display();
+                    io::Error::new(e.kind(), format!("failed to run '{}': {}", path, e))
+                })?;
+
+            if exit_code.success() {
+                Ok(())
+            } else {
+                Err(ExecError::ExitStatus(exit_code))
+            }
         }
+    }
 
-        for entry in self.class_path() {
+    /// Returns the command line to compile `inputs`, as printed before running
+    /// it. In the shared JVM, this is ojavac's main class and its arguments.
+    pub fn javac_command_line<P>(
+        &self,
+        set: &JavaSourceSet,
+        inputs: &[P],
+        output_root: &Path,
+        manifest_path: &Path,
+    ) -> String
+    where
+        P: AsRef<Path>,
+    {
+        if self.data.direct_jvm {
+            let args = self.ojavac_main_args(set, inputs, output_root, manifest_path);
+            return format!("{} {}", jvm::MAIN_CLASS, args.join(" "));
+        }
+
+        let args = self.javac_args(set, inputs, output_root, manifest_path);
+        let map = args.iter().map(|s| s.to_string_lossy()).collect::<Vec<_>>();
+        format!("{} {}", self.data.javac_path.to_string_lossy(), map.join(" "))
+    }
+
+    /// Returns the full ojavac command line to compile `inputs`, one argument
+    /// per line, so that tasks can tell when it changes.
+    pub fn javac_fingerprint<P>(
+        &self,
+        set: &JavaSourceSet,
+        inputs: &[P],
+        output_root: &Path,
+        manifest_path: &Path,
+    ) -> String
+    where
+        P: AsRef<Path>,
+    {
+        let mut s = self.data.javac_path.to_string_lossy().into_owned();
+
+        for arg in self.javac_args(set, inputs, output_root, manifest_path) {
+            s.push('\n');
+            s.push_str(&arg.to_string_lossy());
+        }
+
+        s
+    }
+
+    /// Returns the arguments to the ojavac executable to compile `inputs`.
+    fn javac_args<P>(
+        &self,
+        set: &JavaSourceSet,
+        inputs: &[P],
+        output_root: &Path,
+        manifest_path: &Path,
+    ) -> Vec<OsString>
+    where
+        P: AsRef<Path>,
+    {
+        let mut args: Vec<OsString> = Vec::new();
+
+        for dir in &set.input_dirs {
+            args.push("--include".into());
+            args.push(dir.into());
+        }
+
+        for entry in &set.class_path {
             let path = match entry {
                 ClassPathEntry::File(path) => path,
                 ClassPathEntry::Dir(path) => path,
             };
-            cmd.arg("--link");
-            cmd.arg(&path);
+            args.push("--link".into());
+            args.push(path.into());
         }
 
-        cmd.arg("--out-dir");
-        cmd.arg(output_root);
-        cmd.arg("--manifest");
-        cmd.arg(manifest_path);
-        cmd.arg(input);
+        args.push("--out-dir".into());
+        args.push(output_root.into());
+        args.push("--write-deps".into());
+        args.push(manifest_path.into());
 
-        let map = cmd.get_args().into_iter().map(|s| s.to_string_lossy()).collect::<Vec<_>>();
-        println!("{} {}", self.data.javac_path.to_string_lossy(), map.join(" "));
+        for input in inputs {
+            args.push(input.as_ref().into());
+        }
 
-        let exit_code = cmd.status()?;
+        args
+    }
 
-        if exit_code.success() {
-            Ok(())
-        } else {
-            Err(ExecError::ExitStatus(exit_code))
+    /// Returns the arguments to ojavac's Java main class to compile `inputs`.
+    /// This does the same conversion the ojavac executable does for the
+    /// arguments returned by [`JavaExtension::javac_args()`].
+    fn ojavac_main_args<P>(
+        &self,
+        set: &JavaSourceSet,
+        inputs: &[P],
+        output_root: &Path,
+        manifest_path: &Path,
+    ) -> Vec<String>
+    where
+        P: AsRef<Path>,
+    {
+        let path_str = |path: &Path| path.to_string_lossy().into_owned();
+
+        let include = javacli::build_classpath(&set.input_dirs);
+        let link = javacli::build_classpath(set.class_path.iter().map(|el| match el {
+            ClassPathEntry::File(path) => path,
+            ClassPathEntry::Dir(path) => path,
+        }));
+
+        let mut args = vec!["-implicit:none".to_string()];
+
+        if let Some(include) = include {
+            args.push("-sourcepath".to_string());
+            args.push(include.to_string_lossy().into_owned());
         }
+
+        args.push("-d".to_string());
+        args.push(path_str(output_root));
+
+        let javac_options_len = args.len();
+        args.extend(inputs.iter().map(|el| path_str(el.as_ref())));
+        let inputs_len = args.len() - javac_options_len;
+
+        args.push(link.map_or(".".to_string(), |el| el.to_string_lossy().into_owned()));
+        args.push(javac_options_len.to_string());
+        args.push(inputs_len.to_string());
+        args.push(path_str(manifest_path));
+        args.push(String::new());
+
+        args
     }
 
     pub fn find_class_files(&self, class: &str) -> &[PathBuf] {
         unimplemented!()
     }
 
+    /// Returns the class path of the main source set.
     pub fn class_path(&self) -> Vec<ClassPathEntry> {
-        vec![
-            // ClassPathEntry::Dir(self.project.build_root().join("src"))
-        ]
+        self.data.main_set().class_path.clone()
+    }
+
+    /// Returns the class path to run the main source set's classes with.
+    pub fn runtime_class_path(&self) -> &[ClassPathEntry] {
+        &self.data.runtime_class_path
+    }
+
+    /// Returns whether the project has a test source set, and with it a
+    /// `test` task.
+    pub fn has_tests(&self) -> bool {
+        self.data.sets.iter().any(|el| el.name == sourceset::TEST)
+    }
+
+    /// Returns how to run the project, or `None` if it doesn't have a main
+    /// class. That's the one in the `[run]` table, or the jar's main class.
+    pub fn launch(&self) -> Option<Launch> {
+        let main_class = self
+            .data
+            .run
+            .main_class()
+            .or_else(|| self.data.main_class.as_deref())?;
+
+        Some(Launch::new(
+            &self.data.run,
+            main_class,
+            &self.data.runtime_class_path,
+        ))
     }
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum ClassPathEntry {
     File(PathBuf),
     Dir(PathBuf),
 }
 
-#[derive(Debug, Error)]
-pub enum ExecError {
-    #[error("exit code: {0}")]
-    ExitStatus(ExitStatus),
-    #[error("I/O error: {0}")]
-    Io(#[from] io::Error),
+impl ClassPathEntry {
+    /// Returns the dependency on the files of this entry a program running
+    /// with it sees.
+    pub fn to_input(&self) -> PathDep {
+        match self {
+            ClassPathEntry::File(path) => PathDep::new(path.clone()),
+            // the compiler's bookkeeping files are next to the classes
+            ClassPathEntry::Dir(path) => PathDep::new(path.clone())
+                .with_dir(true, "*")
+                .with_exclude("*.omf")
+                .with_exclude("*.abi")
+                .with_exclude("*.ostate"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::{Path, PathBuf};
+
+    use super::{configured_javac_path, default_javac_path};
+    use crate::testutil::write;
+    use crate::{load_projects, Error};
+
+    #[test]
+    fn finds_ojavac() {
+        let root = Path::new("/build");
+        let find = |build_file: Option<&str>, env: Option<&str>| {
+            configured_javac_path(root, build_file.map(PathBuf::from), env.map(Into::into))
+                .unwrap_or_else(default_javac_path)
+        };
+
+        assert_eq!(
+            find(Some("tools/ojavac"), Some("/env/ojavac")),
+            Path::new("/build/tools/ojavac")
+        );
+        assert_eq!(find(None, Some("/env/ojavac")), Path::new("/env/ojavac"));
+
+        // when not installed, it's next to the executable
+        if cfg!(not(install)) {
+            let mut path = env::current_exe().unwrap();
+            path.set_file_name(format!("ojavac{}", env::consts::EXE_SUFFIX));
+            assert_eq!(find(None, None), path);
+        }
+    }
+
+    #[test]
+    fn rejects_duplicate_classes() {
+        let dir = tempfile::tempdir().unwrap();
+        let build_root = dir.path().join("build");
+        let class = |dir: &Path, name: &str| {
+            write(
+                &dir.join(format!("p/{}.java", name)),
+                &format!("package p;\npublic class {} {{}}\n", name),
+            );
+        };
+
+        write(&build_root.join("obuildroot"), "p\n");
+        write(
+            &build_root.join("p/obuild.toml"),
+            "[project]\nname = \"p\"\nsource_root = \"../..\"\n\n\
+             [java]\nsources = [\"src/main/java\", \"gen\"]\noutput = \"classes\"\n",
+        );
+        class(&dir.path().join("src/main/java"), "A");
+        class(&dir.path().join("gen"), "B");
+
+        let projects = load_projects(&build_root).unwrap();
+        let mut tasks: Vec<_> = projects[0]
+            .create_tasks()
+            .unwrap()
+            .iter()
+            .map(|el| el.name())
+            .filter(|el| el.starts_with("compileJava:"))
+            .collect();
+        tasks.sort();
+
+        assert_eq!(tasks, ["compileJava:p/A", "compileJava:p/B"]);
+
+        class(&dir.path().join("gen"), "A");
+
+        let projects = load_projects(&build_root).unwrap();
+        let err = projects[0].create_tasks().unwrap_err();
+
+        assert!(
+            matches!(&err, Error::DuplicateClass { class, .. } if class == "p/A"),
+            "{}",
+            err
+        );
+    }
 }
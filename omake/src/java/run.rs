@@ -0,0 +1,76 @@
+use std::collections::BTreeMap;
+use std::process::ExitStatus;
+
+use jvmapi::jvm::command::Stdio;
+use jvmapi::jvm::JvmTask;
+use jvmapi::{JvmCommand, ProcessJvm};
+use serde::Deserialize;
+
+use crate::java::plugin::ClassPathEntry;
+use crate::task::ExecError;
+
+/// The `[run]` table of the build file.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RunSettings {
+    /// The class to run, if it's not the jar's main class.
+    main_class: Option<String>,
+    /// Options passed to the JVM, before the main class.
+    #[serde(default)]
+    jvm_args: Vec<String>,
+    /// System properties to set, passed to the JVM as `-Dkey=value`.
+    #[serde(default)]
+    properties: BTreeMap<String, String>,
+}
+
+impl RunSettings {
+    pub fn main_class(&self) -> Option<&str> {
+        self.main_class.as_deref()
+    }
+}
+
+/// A main class and the JVM to run it in.
+#[derive(Debug)]
+pub struct Launch {
+    jvm: ProcessJvm,
+    main_class: String,
+}
+
+impl Launch {
+    pub fn new(settings: &RunSettings, main_class: &str, class_path: &[ClassPathEntry]) -> Self {
+        let mut jvm = ProcessJvm::new();
+
+        jvm.with_java_args(&settings.jvm_args);
+        jvm.with_java_args(
+            settings
+                .properties
+                .iter()
+                .map(|(key, value)| format!("-D{}={}", key, value)),
+        );
+
+        jvm.with_classpath(class_path.iter().map(|el| match el {
+            ClassPathEntry::File(path) => path,
+            ClassPathEntry::Dir(path) => path,
+        }));
+
+        Launch {
+            jvm,
+            main_class: main_class.to_string(),
+        }
+    }
+
+    pub fn main_class(&self) -> &str {
+        &self.main_class
+    }
+
+    /// Runs the main class with `args` and waits for it to exit. The program
+    /// shares our standard streams, so it can be used interactively.
+    pub fn exec(&self, args: &[String]) -> Result<ExitStatus, ExecError> {
+        let mut cmd = JvmCommand::new(&self.jvm, &self.main_class);
+        cmd.args(args)
+            .stdin(Stdio::Inherit)
+            .stdout(Stdio::Inherit)
+            .stderr(Stdio::Inherit);
+
+        Ok(cmd.spawn()?.wait()?)
+    }
+}
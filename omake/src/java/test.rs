@@ -0,0 +1,259 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{fs, io};
+
+use jvmapi::jvm::command::Stdio;
+use jvmapi::jvm::JvmTask;
+use jvmapi::{javacli, JvmCommand, ProcessJvm};
+use serde::Deserialize;
+
+use crate::java::junit::TestReport;
+use crate::java::jvm::{forward_output, JvmPool};
+use crate::java::plugin::{ClassPathEntry, JavaSourceSet};
+use crate::task::{Context, ExecError, PathDep, StaleReason, Task, TaskError};
+
+/// The `[test]` table of the build file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TestSettings {
+    /// The main class that runs the tests. It gets passed the options of the
+    /// JUnit Platform console launcher.
+    #[serde(default = "default_runner")]
+    runner: String,
+    /// Whether to run the tests in a JVM shared with other test tasks
+    /// instead of starting a new one every time. The runner must not call
+    /// `System.exit()` for this, which the JUnit console launcher does.
+    #[serde(default)]
+    direct_jvm: bool,
+}
+
+impl Default for TestSettings {
+    fn default() -> Self {
+        TestSettings {
+            runner: default_runner(),
+            direct_jvm: false,
+        }
+    }
+}
+
+fn default_runner() -> String {
+    "org.junit.platform.console.ConsoleLauncher".to_string()
+}
+
+/// The tests to run, as given on the command line. Each filter is either a
+/// fully qualified class name, or a class name and a method name separated by
+/// `#`. No filters select every test.
+#[derive(Debug, Clone, Default)]
+pub struct TestFilter {
+    filters: Vec<String>,
+}
+
+impl TestFilter {
+    pub fn new(filters: Vec<String>) -> Self {
+        TestFilter { filters }
+    }
+
+    /// Returns the launcher options selecting the tests, or scanning
+    /// `classes_dir` for them if there is no filter.
+    fn launcher_args(&self, classes_dir: &Path) -> Vec<String> {
+        if self.filters.is_empty() {
+            return vec![
+                "--scan-class-path".to_string(),
+                classes_dir.to_string_lossy().into_owned(),
+            ];
+        }
+
+        let mut args = Vec::new();
+
+        for filter in &self.filters {
+            if filter.contains('#') {
+                args.push("--select-method".to_string());
+            } else {
+                args.push("--select-class".to_string());
+            }
+
+            args.push(filter.clone());
+        }
+
+        args
+    }
+}
+
+/// Runs the tests of a source set and writes JUnit XML reports for them.
+#[derive(Debug)]
+pub struct TestTask {
+    set: Arc<JavaSourceSet>,
+    /// Everything the tests need at runtime: the classes and resources of
+    /// the set, and its class path.
+    class_path: Vec<ClassPathEntry>,
+    reports_dir: PathBuf,
+    settings: TestSettings,
+}
+
+impl Task for TestTask {
+    fn name(&self) -> String {
+        "test".to_string()
+    }
+
+    fn make(&self, ctx: &Context) -> Result<(), TaskError> {
+        // Old reports would be counted as results of this run, so start
+        // from an empty directory.
+        match fs::remove_dir_all(&self.reports_dir) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        fs::create_dir_all(&self.reports_dir)?;
+
+        let args = self.launcher_args(ctx);
+
+        let status = if self.settings.direct_jvm {
+            self.run_direct(ctx, &args)?;
+            None
+        } else {
+            Some(self.run_process(&args)?)
+        };
+
+        let report = TestReport::read_dir(&self.reports_dir)?;
+
+        if report.total() > 0 {
+            println!("{}", report.summary());
+        }
+
+        for failure in report.failures() {
+            println!("  FAILED {}", failure);
+        }
+
+        if !report.is_success() {
+            return Err(ExecError::Failed.into());
+        }
+
+        match status {
+            Some(status) if !status.success() => Err(ExecError::ExitStatus(status).into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn inputs(&self, _ctx: &Context) -> Vec<PathDep> {
+        self.class_path.iter().map(ClassPathEntry::to_input).collect()
+    }
+
+    fn outputs(&self, _ctx: &Context) -> Vec<PathDep> {
+        vec![PathDep::new(self.reports_dir.clone()).with_dir(false, "*.xml")]
+    }
+
+    /// Always returns [`StaleReason::NeverSucceeded`]. This only gets asked
+    /// when there is no recorded state, which is only recorded once the tests
+    /// passed, so the tests always run until they do. After that, the
+    /// recorded inputs decide. The reports can't tell us anything here, since
+    /// those of a failed run are just as new as those of a passing one.
+    fn needs_exec(&self, _ctx: &Context) -> Option<StaleReason> {
+        Some(StaleReason::NeverSucceeded)
+    }
+
+    fn fingerprint(&self, ctx: &Context) -> Option<String> {
+        let mut s = self.settings.runner.clone();
+
+        for arg in self.launcher_args(ctx) {
+            s.push('\n');
+            s.push_str(&arg);
+        }
+
+        Some(s)
+    }
+
+    fn commands(&self, ctx: &Context) -> Vec<String> {
+        vec![format!("{} {}", self.settings.runner, self.launcher_args(ctx).join(" "))]
+    }
+
+    fn produced_files(&self, ctx: &Context) -> Vec<PathBuf> {
+        // everything in the reports directory is ours, including the
+        // reports of failed runs, which aren't recorded in the state
+        self.outputs(ctx)
+            .iter()
+            .flat_map(|el| el.paths().map(Cow::into_owned).collect::<Vec<_>>())
+            .collect()
+    }
+}
+
+impl TestTask {
+    pub fn new(
+        set: Arc<JavaSourceSet>,
+        class_path: Vec<ClassPathEntry>,
+        reports_dir: PathBuf,
+        settings: TestSettings,
+    ) -> Self {
+        TestTask {
+            set,
+            class_path,
+            reports_dir,
+            settings,
+        }
+    }
+
+    /// Returns the jars on the class path. The JVM gets started with these,
+    /// so that the runner can be loaded from them.
+    fn jars(&self) -> Vec<PathBuf> {
+        self.class_path
+            .iter()
+            .filter_map(|el| match el {
+                ClassPathEntry::File(path) => Some(path.clone()),
+                ClassPathEntry::Dir(_) => None,
+            })
+            .collect()
+    }
+
+    /// Returns the arguments to the runner. The directories on the class path
+    /// are passed here instead of to the JVM, since the runner loads them in
+    /// a class loader of its own, which sees classes recompiled since the
+    /// JVM started.
+    fn launcher_args(&self, ctx: &Context) -> Vec<String> {
+        let dirs = self.class_path.iter().filter_map(|el| match el {
+            ClassPathEntry::File(_) => None,
+            ClassPathEntry::Dir(path) => Some(path),
+        });
+
+        let mut args = vec!["--disable-banner".to_string()];
+
+        if let Some(class_path) = javacli::build_classpath(dirs) {
+            args.push("--class-path".to_string());
+            args.push(class_path.to_string_lossy().into_owned());
+        }
+
+        args.push("--reports-dir".to_string());
+        args.push(self.reports_dir.to_string_lossy().into_owned());
+
+        let filter = ctx.get_extension::<TestFilter>().cloned().unwrap_or_default();
+        args.extend(filter.launcher_args(self.set.output_dir()));
+
+        args
+    }
+
+    fn run_process(&self, args: &[String]) -> Result<std::process::ExitStatus, ExecError> {
+        let mut jvm = ProcessJvm::new();
+        jvm.with_classpath(self.jars());
+
+        let mut cmd = JvmCommand::new(&jvm, &self.settings.runner);
+        cmd.args(args).stdout(Stdio::Inherit).stderr(Stdio::Inherit);
+
+        Ok(cmd.spawn()?.wait()?)
+    }
+
+    /// Runs the tests in a pooled JVM. These don't report an exit status,
+    /// the results only come from the reports.
+    fn run_direct(&self, ctx: &Context, args: &[String]) -> Result<(), ExecError> {
+        let jvms = ctx.get_extension::<Arc<JvmPool>>().unwrap();
+
+        jvms.with_jvm(&self.jars(), |jvm| {
+            let mut cmd = JvmCommand::new(jvm, &self.settings.runner);
+            cmd.args(args).stdout(Stdio::Piped).stderr(Stdio::Piped);
+            let mut task = cmd.spawn()?;
+
+            forward_output(&mut task)?;
+            task.wait()?;
+
+            Ok(())
+        })
+    }
+}
@@ -2,54 +2,617 @@
 #![feature(generators)]
 #![feature(generator_trait)]
 
+use std::collections::BTreeSet;
 use std::path::{Path, PathBuf};
-use std::{fs, io};
+use std::process::exit;
+use std::sync::Arc;
+use std::{env, fs, io, thread};
 
+use clap::{app_from_crate, App, Arg, ArgMatches};
+use petgraph::algo::toposort;
+use petgraph::graph::DiGraph;
 use thiserror::Error;
 
+use crate::java::jvm::{self, JvmPool};
+use crate::java::plugin::JavaExtension;
+use crate::java::test::TestFilter;
 use crate::project::Project;
-use crate::java::plugin::JavaPlugin;
-use crate::plugin::Plugin;
-use crate::task::Context;
+use crate::task::{
+    BuildReport, Context, CycleError, Scheduler, Task, TaskError, TaskGraph, TaskNode,
+};
 
 mod java;
 mod plugin;
 mod project;
+mod resources;
+mod run;
+mod sourceset;
 mod task;
+#[cfg(test)]
+mod testutil;
+mod watch;
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
 fn main() {
-    let path = Path::new("/home/saiko/src/origami/test/build");
-
-    let dirs = collect_directories(path).expect("failed to load build root");
-    let projects = dirs
-        .into_iter()
-        .map(|el| project::load_project(&el))
-        .try_fold(Vec::new(), |mut acc, a| match a {
-            Ok(a) => {
-                acc.push(a);
-                Ok(acc)
+    let matches = match app().try_get_matches() {
+        Ok(matches) if matches.subcommand().is_some() => matches,
+        // Without a subcommand, build. Parse again with it inserted, so that
+        // the build options are accepted too.
+        result => {
+            let args = env::args_os()
+                .take(1)
+                .chain(Some("build".into()))
+                .chain(env::args_os().skip(1));
+
+            match app().try_get_matches_from(args) {
+                Ok(matches) => matches,
+                // report what's wrong with the arguments as they were given
+                Err(e) => result.err().unwrap_or(e).exit(),
+            }
+        }
+    };
+
+    let (command, sub_matches) = matches.subcommand().unwrap();
+    let mut selection = Selection::from_matches(sub_matches);
+
+    let cwd = env::current_dir().unwrap_or_default();
+
+    let build_root = match sub_matches.value_of("build-root") {
+        // make this absolute so that paths written by the compiler match ours
+        Some(path) => cwd.join(path),
+        None => {
+            match find_build_root(&cwd) {
+                Some(path) => path,
+                None => {
+                    eprintln!(
+                        "error: could not find 'obuildroot' in '{}' or any parent directory",
+                        cwd.display()
+                    );
+                    exit(1);
+                }
+            }
+        }
+    };
+
+    let mut projects = match load_projects(&build_root) {
+        Ok(projects) => projects,
+        Err(e) => {
+            eprintln!(
+                "error: failed to load build root '{}': {}",
+                build_root.display(),
+                e
+            );
+            exit(1);
+        }
+    };
+
+    for name in &selection.projects {
+        if !projects.iter().any(|p| p.name() == name) {
+            eprintln!("error: no project named '{}' in build root", name);
+            exit(1);
+        }
+    }
+
+    let code = match command {
+        "build" => {
+            let scheduler =
+                scheduler_from_matches(sub_matches).with_dry_run(sub_matches.is_present("dry-run"));
+
+            build_exit_code(build(&projects, &selection, &scheduler))
+        }
+        "test" => {
+            let scheduler = scheduler_from_matches(sub_matches);
+            let filter = TestFilter::new(
+                sub_matches
+                    .values_of("tests")
+                    .into_iter()
+                    .flatten()
+                    .map(str::to_string)
+                    .collect(),
+            );
+
+            for project in &mut projects {
+                project.extensions_mut().insert(filter.clone());
+            }
+
+            selection.tasks = vec!["test".to_string()];
+
+            build_exit_code(test(&projects, &selection, &scheduler))
+        }
+        "run" => {
+            let scheduler = scheduler_from_matches(sub_matches);
+            let args: Vec<_> = sub_matches
+                .values_of("args")
+                .into_iter()
+                .flatten()
+                .map(str::to_string)
+                .collect();
+
+            match run::run(&projects, &selection, &scheduler, &args) {
+                Ok(Some(status)) => status.code().unwrap_or(1),
+                Ok(None) => 1,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    1
+                }
+            }
+        }
+        "watch" => {
+            let scheduler = scheduler_from_matches(sub_matches);
+            error_exit_code(watch::watch(&projects, &selection, &scheduler))
+        }
+        "clean" => error_exit_code(clean(&projects, &selection)),
+        "tasks" => error_exit_code(task_list(&projects, &selection).map(|s| print!("{}", s))),
+        "graph" => {
+            let graph = task_graph(&projects, |node| {
+                selection.includes_project(node.project()) && selection.includes_task(node.task())
+            });
+
+            error_exit_code(graph.map(|graph| print!("{}", graph.to_dot())))
+        }
+        _ => unreachable!(),
+    };
+
+    // exit() doesn't run destructors, so stop the JVMs the build started
+    // explicitly
+    jvm::shutdown(&projects);
+    exit(code);
+}
+
+/// Returns the exit code for the result of a build, printing the error if
+/// there is one.
+fn build_exit_code(result: Result<bool>) -> i32 {
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+/// Returns the exit code for the result of a command, printing the error if
+/// there is one.
+fn error_exit_code(result: Result<()>) -> i32 {
+    build_exit_code(result.map(|_| true))
+}
+
+/// Returns the command line interface.
+fn app<'a>() -> App<'a> {
+    app_from_crate!()
+        .args(&[
+            Arg::new("build-root")
+                .short('C')
+                .long("build-root")
+                .value_name("PATH")
+                .global(true)
+                .help("path to the build root (default: searched for upwards from the current directory)"),
+            Arg::new("project")
+                .short('p')
+                .long("project")
+                .value_name("NAME")
+                .multiple_occurrences(true)
+                .global(true)
+                .help("only operate on the given project"),
+        ])
+        .subcommands([
+            App::new("build")
+                .about("build the selected tasks (default)")
+                .args(&[
+                    task_arg(),
+                    jobs_arg(),
+                    keep_going_arg(),
+                    explain_arg(),
+                    Arg::new("dry-run")
+                        .short('n')
+                        .long("dry-run")
+                        .help("print the commands of the tasks that are out of date without running them"),
+                ]),
+            App::new("watch")
+                .about("build the selected tasks whenever their inputs change")
+                .args(&[task_arg(), jobs_arg(), keep_going_arg(), explain_arg()]),
+            App::new("test")
+                .about("build and run the tests of the selected projects")
+                .args(&[
+                    Arg::new("tests")
+                        .short('t')
+                        .long("tests")
+                        .value_name("FILTER")
+                        .multiple_occurrences(true)
+                        .help("only run the given test class, or method if written as CLASS#METHOD"),
+                    jobs_arg(),
+                    keep_going_arg(),
+                    explain_arg(),
+                ]),
+            App::new("run")
+                .about("build the selected project and run its main class")
+                .args(&[
+                    jobs_arg(),
+                    keep_going_arg(),
+                    explain_arg(),
+                    Arg::new("args")
+                        .value_name("ARGS")
+                        .multiple_occurrences(true)
+                        .last(true)
+                        .help("arguments passed to the program"),
+                ]),
+            App::new("clean")
+                .about("remove the outputs of the selected tasks")
+                .arg(task_arg()),
+            App::new("tasks")
+                .about("list the selected tasks with their inputs and outputs, and whether they are out of date")
+                .arg(task_arg()),
+            App::new("graph")
+                .about("print the graph of the selected tasks and their dependencies in DOT format")
+                .arg(task_arg()),
+        ])
+}
+
+/// Builds the selected tasks, returning whether all of them succeeded.
+fn build(projects: &[Project], selection: &Selection, scheduler: &Scheduler) -> Result<bool> {
+    build_matching(projects, scheduler, |node| {
+        selection.includes_project(node.project()) && selection.includes_task(node.task())
+    })
+}
+
+/// Runs the tests of the selected projects, returning whether they passed.
+fn test(projects: &[Project], selection: &Selection, scheduler: &Scheduler) -> Result<bool> {
+    let has_tests = projects
+        .iter()
+        .filter(|el| selection.includes_project(el))
+        .filter_map(|el| el.extensions().get::<JavaExtension>())
+        .any(JavaExtension::has_tests);
+
+    // otherwise the build would succeed without having done anything
+    if !has_tests {
+        return Err(Error::NoTests);
+    }
+
+    build(projects, selection, scheduler)
+}
+
+/// Builds the tasks `pred` returns true for and the tasks they depend on,
+/// returning whether all of them succeeded.
+fn build_matching<F>(projects: &[Project], scheduler: &Scheduler, pred: F) -> Result<bool>
+where
+    F: FnMut(&TaskNode) -> bool,
+{
+    // a dry run must not touch any files
+    if !scheduler.is_dry_run() {
+        for project in projects {
+            project.remove_stale_outputs()?;
+        }
+    }
+
+    let graph = task_graph(projects, pred)?;
+    let report = scheduler.run(&graph)?;
+
+    if scheduler.is_dry_run() {
+        println!("{} of {} task(s) would run", report.ran, report.total);
+    }
+
+    if !report.is_success() {
+        eprint!("{}", failure_summary(&report));
+    }
+
+    Ok(report.is_success())
+}
+
+/// Returns the list of failed tasks printed after a build that failed.
+fn failure_summary(report: &BuildReport) -> String {
+    let mut s = format!("error: {} task(s) failed:\n", report.failures.len());
+
+    for failure in &report.failures {
+        s.push_str(&format!("  {}\n", failure));
+    }
+
+    if report.skipped() > 0 {
+        s.push_str(&format!(
+            "note: {} task(s) were not run\n",
+            report.skipped()
+        ));
+    }
+
+    s
+}
+
+/// Returns the graph of the tasks `pred` returns true for and the tasks they
+/// depend on.
+fn task_graph<F>(projects: &[Project], pred: F) -> Result<TaskGraph>
+where
+    F: FnMut(&TaskNode) -> bool,
+{
+    let mut graph = TaskGraph::new();
+
+    for project in projects {
+        graph.add_project(project)?;
+    }
+
+    graph.link();
+    graph.select(pred);
+
+    Ok(graph)
+}
+
+fn clean(projects: &[Project], selection: &Selection) -> Result<()> {
+    for project in projects.iter().filter(|p| selection.includes_project(p)) {
+        let ctx = Context::new(project);
+        let mut paths = BTreeSet::new();
+
+        // Outputs of tasks that don't exist anymore, such as the classes of
+        // deleted source files, are only known to the plugins.
+        if selection.tasks.is_empty() {
+            project.remove_stale_outputs()?;
+        }
+
+        for task in project.create_tasks()? {
+            if selection.includes_task(&*task) {
+                paths.extend(cleaned_paths(&*task, &ctx));
+            }
+        }
+
+        for path in &paths {
+            match fs::remove_file(path) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => eprintln!("warning: failed to remove '{}': {}", path.display(), e),
+            }
+        }
+
+        // This also has the state of tasks that don't exist anymore. It's
+        // all ours, so it can go when cleaning everything.
+        if selection.tasks.is_empty() {
+            let state_dir = task::state_dir(project.build_root());
+
+            match fs::remove_dir_all(&state_dir) {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => eprintln!("warning: failed to remove '{}': {}", state_dir.display(), e),
+            }
+
+            paths.insert(state_dir);
+        }
+
+        prune_empty_dirs(project.build_root(), &paths);
+    }
+
+    Ok(())
+}
+
+/// Returns the selected tasks of each project and whether they are out of
+/// date, followed by their inputs (`<-`), soft inputs (`<~`) and outputs
+/// (`->`).
+fn task_list(projects: &[Project], selection: &Selection) -> Result<String> {
+    let mut s = String::new();
+
+    for project in projects.iter().filter(|p| selection.includes_project(p)) {
+        let ctx = Context::new(project);
+        s.push_str(&format!("{}:\n", project.name()));
+
+        for task in project.create_tasks()? {
+            if !selection.includes_task(&*task) {
+                continue;
+            }
+
+            match ctx.stale_reason(&*task) {
+                None => s.push_str(&format!("  {} (up to date)\n", task.name())),
+                Some(reason) => {
+                    s.push_str(&format!("  {} (out of date: {})\n", task.name(), reason))
+                }
+            }
+
+            for input in task.inputs(&ctx) {
+                s.push_str(&format!("    <- {}\n", input));
+            }
+
+            for input in task.soft_inputs(&ctx) {
+                s.push_str(&format!("    <~ {}\n", input));
             }
-            Err(e) => Err(e),
-        })
-        .expect("failed to load build directories");
 
-    for mut project in projects {
-        java::plugin::register(&mut project);
+            for output in task.outputs(&ctx) {
+                s.push_str(&format!("    -> {}\n", output));
+            }
+        }
+    }
+
+    Ok(s)
+}
+
+fn task_arg<'a>() -> Arg<'a> {
+    Arg::new("task")
+        .value_name("TASK")
+        .multiple_occurrences(true)
+        .help("only operate on the given tasks (default: all tasks)")
+}
+
+fn jobs_arg<'a>() -> Arg<'a> {
+    Arg::new("jobs")
+        .short('j')
+        .long("jobs")
+        .value_name("N")
+        .help("run up to N tasks at once (default: number of CPUs)")
+}
+
+fn keep_going_arg<'a>() -> Arg<'a> {
+    Arg::new("keep-going")
+        .short('k')
+        .long("keep-going")
+        .help("keep running tasks that don't depend on failed ones after a failure")
+}
+
+fn explain_arg<'a>() -> Arg<'a> {
+    Arg::new("explain")
+        .long("explain")
+        .help("print why each task that runs is out of date")
+}
 
-        let tasks = project.create_tasks();
+fn scheduler_from_matches(matches: &ArgMatches) -> Scheduler {
+    let jobs = match matches.value_of("jobs").map(str::parse) {
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+        Some(Ok(jobs)) => jobs,
+        Some(Err(e)) => {
+            eprintln!("error: invalid job count: {}", e);
+            exit(1);
+        }
+    };
 
-        let ctx = Context::new(&project);
+    Scheduler::new(jobs)
+        .with_keep_going(matches.is_present("keep-going"))
+        .with_explain(matches.is_present("explain"))
+}
 
-        // println!("tasks = {:#?}", tasks);
+/// The projects and tasks selected on the command line. Empty lists select
+/// everything.
+#[derive(Debug, Default)]
+struct Selection {
+    projects: Vec<String>,
+    tasks: Vec<String>,
+}
 
-        for task in tasks {
-            if task.needs_exec(&ctx) {
-                task.make(&ctx).unwrap();
+impl Selection {
+    fn from_matches(matches: &ArgMatches) -> Self {
+        let values = |name| {
+            // not every subcommand selects tasks
+            if !matches.is_valid_arg(name) {
+                return Vec::new();
             }
+
+            matches
+                .values_of(name)
+                .into_iter()
+                .flatten()
+                .map(str::to_string)
+                .collect()
+        };
+
+        Selection {
+            projects: values("project"),
+            tasks: values("task"),
+        }
+    }
+
+    fn includes_project(&self, project: &Project) -> bool {
+        self.projects.is_empty() || self.projects.iter().any(|el| el == project.name())
+    }
+
+    /// Returns whether the task is selected. A filter selects a task if it is
+    /// equal to the task's name, or if it is the part of the task's name
+    /// before a `:`, so that `compileJava` selects every `compileJava:...`
+    /// task.
+    fn includes_task(&self, task: &dyn Task) -> bool {
+        if self.tasks.is_empty() {
+            return true;
+        }
+
+        let name = task.name();
+
+        self.tasks.iter().any(|filter| {
+            name == *filter
+                || matches!(name.strip_prefix(filter.as_str()), Some(rest) if rest.starts_with(':'))
+        })
+    }
+}
+
+/// Searches `start` and all its parent directories for a build root, that
+/// is, a directory containing an `obuildroot` file.
+fn find_build_root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|el| el.join("obuildroot").is_file())
+        .map(Path::to_path_buf)
+}
+
+fn load_projects(build_root: &Path) -> Result<Vec<Project>> {
+    let mut loaded = Vec::new();
+    let jvms = Arc::new(JvmPool::default());
+
+    for dir in collect_directories(build_root)? {
+        let mut project = project::load_project(&dir)?;
+        project.extensions_mut().insert(jvms.clone());
+        loaded.push(Some(project));
+    }
+
+    // Register plugins in dependency order, so that a project's plugins can
+    // look at the projects it depends on.
+    let order = project_order(&loaded)?;
+    let mut projects: Vec<Project> = Vec::with_capacity(loaded.len());
+
+    for idx in order {
+        let mut project = loaded[idx].take().unwrap();
+        let dependencies: Vec<_> = project
+            .dependencies()
+            .iter()
+            .map(|name| projects.iter().find(|el| el.name() == name).unwrap())
+            .collect();
+
+        resources::plugin::register(&mut project)?;
+        java::plugin::register(&mut project, &dependencies)?;
+        projects.push(project);
+    }
+
+    Ok(projects)
+}
+
+/// Returns the indices of the projects sorted so that every project comes
+/// after the projects it depends on.
+fn project_order(projects: &[Option<Project>]) -> Result<Vec<usize>> {
+    let projects: Vec<_> = projects.iter().map(|el| el.as_ref().unwrap()).collect();
+    let mut graph = DiGraph::<usize, ()>::new();
+    let nodes: Vec<_> = (0..projects.len()).map(|idx| graph.add_node(idx)).collect();
+
+    for (idx, project) in projects.iter().enumerate() {
+        for dependency in project.dependencies() {
+            let dep_idx = projects
+                .iter()
+                .position(|el| el.name() == dependency)
+                .ok_or_else(|| Error::UnknownProject {
+                    project: project.name().to_string(),
+                    dependency: dependency.clone(),
+                })?;
+
+            graph.add_edge(nodes[dep_idx], nodes[idx], ());
         }
     }
+
+    match toposort(&graph, None) {
+        Ok(order) => Ok(order.into_iter().map(|el| graph[el]).collect()),
+        Err(cycle) => Err(Error::ProjectCycle(
+            projects[graph[cycle.node_id()]].name().to_string(),
+        )),
+    }
+}
+
+/// Returns the files to remove when cleaning `task`: everything it says it
+/// produced, the outputs recorded the last time it ran, and the file the
+/// state was recorded in.
+fn cleaned_paths(task: &dyn Task, ctx: &Context) -> Vec<PathBuf> {
+    let mut vec = task.produced_files(ctx);
+
+    if let Some(state) = ctx.load_state(task) {
+        vec.extend(state.outputs().map(Path::to_path_buf));
+    }
+
+    vec.push(task.state_path(ctx));
+    vec
+}
+
+/// Removes the directories `paths` were in if they are empty now, along with
+/// their parents up to, but not including, `root`.
+fn prune_empty_dirs(root: &Path, paths: &BTreeSet<PathBuf>) {
+    let dirs: BTreeSet<_> = paths
+        .iter()
+        .flat_map(|el| el.ancestors().skip(1))
+        .filter(|el| el.starts_with(root) && *el != root)
+        .collect();
+
+    // children sort after their parents, so go backwards to remove them
+    // first
+    for dir in dirs.into_iter().rev() {
+        // fails if there's something left in it, which is fine
+        let _ = fs::remove_dir(dir);
+    }
 }
 
 fn collect_directories(path: &Path) -> io::Result<Vec<PathBuf>> {
@@ -74,4 +637,246 @@ pub enum Error {
     Io(#[from] io::Error),
     #[error("parse error: {0}")]
     Parse(#[from] toml::de::Error),
+    #[error("failed to read lockfile '{0}': {1}")]
+    Lockfile(PathBuf, String),
+    #[error("project '{project}' depends on '{dependency}', which is not in the build root")]
+    UnknownProject { project: String, dependency: String },
+    #[error("dependency cycle between projects involving '{0}'")]
+    ProjectCycle(String),
+    #[error("no selected project has a main class to run, set 'main_class' in its [run] table")]
+    NoMainClass,
+    #[error("no test tasks found, none of the selected projects has a test source set")]
+    NoTests,
+    #[error("more than one project has a main class to run, select one with -p: {}", .0.join(", "))]
+    AmbiguousMainClass(Vec<String>),
+    #[error("output directories '{first}' and '{second}' of different source sets overlap")]
+    OverlappingOutputs { first: PathBuf, second: PathBuf },
+    #[error("class '{class}' is defined in both '{first}' and '{second}'")]
+    DuplicateClass {
+        class: String,
+        first: PathBuf,
+        second: PathBuf,
+    },
+    #[error("{0}")]
+    Cycle(#[from] CycleError),
+    #[error("task '{task}' in project '{project}' failed: {source}")]
+    Task {
+        project: String,
+        task: String,
+        source: TaskError,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::java::jvm;
+    use crate::task::Scheduler;
+    use crate::testutil::write;
+    use crate::{
+        build, build_exit_code, clean, failure_summary, load_projects, task_graph, task_list,
+        Selection,
+    };
+
+    #[test]
+    fn reports_failed_tasks() {
+        let dir = tempfile::tempdir().unwrap();
+        let build_root = dir.path().join("build");
+
+        write(&build_root.join("obuildroot"), "p\n");
+        write(
+            &build_root.join("p/obuild.toml"),
+            "[project]\nname = \"p\"\nsource_root = \"../..\"\n",
+        );
+        write(
+            &dir.path().join("src/p/A.java"),
+            "package p;\npublic class A {\n",
+        );
+        write(
+            &dir.path().join("src/p/B.java"),
+            "package p;\npublic class B {}\n",
+        );
+
+        let projects = load_projects(&build_root).unwrap();
+        let graph = task_graph(&projects, |_| true).unwrap();
+        let report = Scheduler::new(1).with_keep_going(true).run(&graph).unwrap();
+        let summary = failure_summary(&report);
+
+        let mut lines = summary.lines();
+        assert_eq!(lines.next(), Some("error: 1 task(s) failed:"));
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("  task 'compileJava:p/A' in project 'p' failed: "));
+        // the jar needs A's class
+        assert_eq!(lines.next(), Some("note: 1 task(s) were not run"));
+        assert_eq!(lines.next(), None);
+
+        let selection = Selection::default();
+        let scheduler = Scheduler::new(1);
+        assert_eq!(build_exit_code(build(&projects, &selection, &scheduler)), 1);
+
+        write(
+            &dir.path().join("src/p/A.java"),
+            "package p;\npublic class A {}\n",
+        );
+        assert_eq!(build_exit_code(build(&projects, &selection, &scheduler)), 0);
+
+        jvm::shutdown(&projects);
+    }
+
+    #[test]
+    fn clean_only_removes_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let build_root = dir.path().join("build");
+        let p = build_root.join("p");
+
+        write(&build_root.join("obuildroot"), "p\n");
+        write(
+            &p.join("obuild.toml"),
+            "[project]\nname = \"p\"\nsource_root = \"../..\"\n",
+        );
+        write(
+            &dir.path().join("src/p/A.java"),
+            "package p;\npublic class A {}\n",
+        );
+        write(&dir.path().join("resources/a.txt"), "a");
+
+        let projects = load_projects(&build_root).unwrap();
+        let scheduler = Scheduler::new(1);
+        assert!(build(&projects, &Selection::default(), &scheduler).unwrap());
+        jvm::shutdown(&projects);
+
+        // files that happen to be next to the outputs
+        write(&p.join("src/p/notes.txt"), "");
+        write(&p.join("resources/b.txt"), "");
+        write(&p.join("README"), "");
+
+        clean(&projects, &Selection::default()).unwrap();
+
+        let removed = [
+            "src/p/A.class",
+            "src/p/A.omf",
+            "resources/a.txt",
+            "libs",
+            ".omake",
+        ];
+        let kept = [
+            "obuild.toml",
+            "src/p/notes.txt",
+            "resources/b.txt",
+            "README",
+        ];
+
+        for path in removed {
+            assert!(!p.join(path).exists(), "{}", path);
+        }
+
+        for path in kept {
+            assert!(p.join(path).is_file(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn lists_tasks() {
+        let dir = tempfile::tempdir().unwrap();
+        let build_root = dir.path().join("build");
+        let input = dir.path().join("resources/a.txt");
+        let output = build_root.join("p/resources/a.txt");
+
+        write(&build_root.join("obuildroot"), "p\n");
+        write(
+            &build_root.join("p/obuild.toml"),
+            "[project]\nname = \"p\"\nsource_root = \"../..\"\n",
+        );
+        write(&input, "a");
+
+        let projects = load_projects(&build_root).unwrap();
+        let selection = Selection {
+            projects: Vec::new(),
+            tasks: vec!["processResources".to_string()],
+        };
+        let expected = |state: String| {
+            format!(
+                "p:\n  processResources:a.txt ({})\n    <- {}\n    -> {}\n",
+                state,
+                input.display(),
+                output.display()
+            )
+        };
+
+        assert_eq!(
+            task_list(&projects, &selection).unwrap(),
+            expected(format!(
+                "out of date: output '{}' is missing",
+                output.display()
+            ))
+        );
+
+        assert!(build(&projects, &selection, &Scheduler::new(1)).unwrap());
+
+        assert_eq!(
+            task_list(&projects, &selection).unwrap(),
+            expected("up to date".to_string())
+        );
+    }
+
+    #[test]
+    fn dry_run_writes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let build_root = dir.path().join("build");
+        let p = build_root.join("p");
+
+        write(&build_root.join("obuildroot"), "p\n");
+        write(
+            &p.join("obuild.toml"),
+            "[project]\nname = \"p\"\nsource_root = \"../..\"\n",
+        );
+        write(
+            &dir.path().join("src/p/A.java"),
+            "package p;\npublic class A {}\n",
+        );
+        write(&dir.path().join("resources/a.txt"), "a");
+
+        let projects = load_projects(&build_root).unwrap();
+        let selection = Selection::default();
+        let dry_run = Scheduler::new(1).with_dry_run(true);
+
+        assert!(build(&projects, &selection, &dry_run).unwrap());
+
+        let files: Vec<_> = fs::read_dir(&p)
+            .unwrap()
+            .map(|el| el.unwrap().file_name())
+            .collect();
+        assert_eq!(files, ["obuild.toml"]);
+
+        // nothing changes when there are outputs already either
+        assert!(build(&projects, &selection, &Scheduler::new(1)).unwrap());
+        jvm::shutdown(&projects);
+
+        let read_state = || {
+            let mut vec: Vec<_> = fs::read_dir(p.join(".omake/state"))
+                .unwrap()
+                .map(|el| fs::read(el.unwrap().path()).unwrap())
+                .collect();
+            vec.sort();
+            vec
+        };
+
+        let state = read_state();
+        let class = fs::read(p.join("src/p/A.class")).unwrap();
+
+        write(
+            &dir.path().join("src/p/A.java"),
+            "package p;\npublic class A { int a; }\n",
+        );
+        fs::remove_file(dir.path().join("resources/a.txt")).unwrap();
+
+        assert!(build(&projects, &selection, &dry_run).unwrap());
+
+        assert!(read_state() == state, "the task state changed");
+        assert_eq!(fs::read(p.join("src/p/A.class")).unwrap(), class);
+        assert!(p.join("resources/a.txt").is_file());
+    }
 }
@@ -1,6 +1,16 @@
+use std::io;
+
 use crate::project::Project;
 use crate::task::Task;
+use crate::Result;
+
+pub trait Plugin: Send + Sync {
+    fn create_tasks(&self, project: &Project) -> Result<Vec<Box<dyn Task>>>;
 
-pub trait Plugin {
-    fn create_tasks(&self, project: &Project) -> Vec<Box<dyn Task>>;
+    /// Removes files this plugin produced in an earlier build that no task
+    /// produces anymore, for example because their source file was deleted.
+    /// Gets called before building.
+    fn remove_stale_outputs(&self, _project: &Project) -> io::Result<()> {
+        Ok(())
+    }
 }
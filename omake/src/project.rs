@@ -1,8 +1,8 @@
-use std::fs;
-use std::path::{Path, PathBuf};
+use std::{fs, io};
+use std::path::{Component, Path, PathBuf};
 
 use serde::Deserialize;
-use type_map::TypeMap;
+use type_map::concurrent::TypeMap;
 
 use crate::plugin::Plugin;
 use crate::Result;
@@ -10,8 +10,10 @@ use crate::task::Task;
 
 pub struct Project {
     name: String,
+    version: Option<String>,
     source_root: PathBuf,
     build_root: PathBuf,
+    dependencies: Vec<String>,
     build_file: toml::Value,
     extensions: TypeMap,
     plugins: Vec<Box<dyn Plugin>>,
@@ -22,6 +24,10 @@ impl Project {
         &self.name
     }
 
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
     pub fn source_root(&self) -> &Path {
         &self.source_root
     }
@@ -30,18 +36,32 @@ impl Project {
         &self.build_root
     }
 
+    /// Returns the names of the projects in the same build root this project
+    /// depends on.
+    pub fn dependencies(&self) -> &[String] {
+        &self.dependencies
+    }
+
     pub fn build_file(&self) -> &toml::Value {
         &self.build_file
     }
 
-    pub(crate) fn create_tasks(&self) -> Vec<Box<dyn Task>> {
+    pub(crate) fn create_tasks(&self) -> Result<Vec<Box<dyn Task>>> {
         let mut vec = Vec::new();
 
         for plugin in self.plugins.iter() {
-            vec.extend(plugin.create_tasks(self));
+            vec.extend(plugin.create_tasks(self)?);
+        }
+
+        Ok(vec)
+    }
+
+    pub(crate) fn remove_stale_outputs(&self) -> io::Result<()> {
+        for plugin in self.plugins.iter() {
+            plugin.remove_stale_outputs(self)?;
         }
 
-        vec
+        Ok(())
     }
 
     pub fn extensions(&self) -> &TypeMap {
@@ -73,25 +93,85 @@ pub fn load_project(path: &Path) -> Result<Project> {
     #[derive(Deserialize)]
     struct MinProject {
         name: String,
+        version: Option<String>,
         plugins: Option<Vec<String>>,
         source_root: PathBuf,
+        #[serde(default)]
+        dependencies: Vec<String>,
     }
 
     let Min {
         project:
             MinProject {
                 name,
+                version,
                 plugins,
                 source_root,
+                dependencies,
             },
-    } = Min::deserialize(v.clone()).unwrap();
+    } = Min::deserialize(v.clone())?;
 
     Ok(Project {
         name,
-        source_root,
-        build_root: path.to_path_buf(),
+        version,
+        // relative source roots are relative to the project's build directory
+        source_root: normalize(&path.join(source_root)),
+        build_root: normalize(path),
+        dependencies,
         build_file: v,
         extensions: TypeMap::default(),
         plugins: Vec::new(),
     })
 }
+
+/// Removes `.` and `..` components from `path` without looking at the file
+/// system, the same way the compiler does for the paths in its manifests.
+/// A relative path keeps the `..` components going above where it starts.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match out.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    out.pop();
+                }
+                // there's nothing above the root
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => out.push(component),
+            },
+            _ => out.push(component),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::normalize;
+
+    #[test]
+    fn normalizes_paths() {
+        let cases = [
+            ("a/./b", "a/b"),
+            ("a/../b", "b"),
+            ("a/b/../../c", "c"),
+            ("..", ".."),
+            ("../..", "../.."),
+            ("a/../../b", "../b"),
+            ("../a/../..", "../.."),
+            ("/a/../b", "/b"),
+            ("/..", "/"),
+            (".", ""),
+        ];
+
+        for (path, expected) in cases {
+            let normalized = normalize(Path::new(path));
+            assert_eq!(normalized, PathBuf::from(expected), "{}", path);
+        }
+    }
+}
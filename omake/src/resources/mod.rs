@@ -0,0 +1,166 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::resources::plugin::ResourcesExtension;
+use crate::task::{input_changed, Context, PathDep, StaleReason, Task, TaskError};
+
+pub mod plugin;
+
+/// Copies a single resource file into the resource output directory,
+/// expanding property placeholders in it if enabled.
+#[derive(Debug)]
+pub struct ResourceTask {
+    /// The name of the task without the path, which depends on the source
+    /// set.
+    task_name: String,
+    input_root: PathBuf,
+    output_root: PathBuf,
+    path: PathBuf,
+}
+
+impl Task for ResourceTask {
+    fn name(&self) -> String {
+        format!("{}:{}", self.task_name, self.path.to_string_lossy())
+    }
+
+    fn make(&self, ctx: &Context) -> Result<(), TaskError> {
+        let c = ctx.get_extension::<ResourcesExtension>().unwrap();
+        let input_file = self.input_file();
+        let output_file = self.output_file();
+
+        if let Some(dir) = output_file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        if !c.expand() {
+            fs::copy(&input_file, &output_file)?;
+            return Ok(());
+        }
+
+        // Only text files can contain placeholders, binary files such as
+        // textures get copied as they are.
+        match String::from_utf8(fs::read(&input_file)?) {
+            Ok(text) => fs::write(&output_file, c.expand_properties(&text))?,
+            Err(e) => fs::write(&output_file, e.into_bytes())?,
+        }
+
+        Ok(())
+    }
+
+    fn inputs(&self, _ctx: &Context) -> Vec<PathDep> {
+        vec![PathDep::new(self.input_file())]
+    }
+
+    fn outputs(&self, _ctx: &Context) -> Vec<PathDep> {
+        vec![PathDep::new(self.output_file())]
+    }
+
+    fn needs_exec(&self, _ctx: &Context) -> Option<StaleReason> {
+        input_changed(&self.input_file(), &self.output_file()).unwrap_or_else(|e| Some(e.into()))
+    }
+
+    fn fingerprint(&self, ctx: &Context) -> Option<String> {
+        let c = ctx.get_extension::<ResourcesExtension>().unwrap();
+        Some(c.fingerprint())
+    }
+
+    fn commands(&self, ctx: &Context) -> Vec<String> {
+        let c = ctx.get_extension::<ResourcesExtension>().unwrap();
+        let verb = if c.expand() { "expand" } else { "copy" };
+
+        vec![format!(
+            "{} '{}' to '{}'",
+            verb,
+            self.input_file().display(),
+            self.output_file().display()
+        )]
+    }
+}
+
+impl ResourceTask {
+    fn new(task_name: String, input_root: PathBuf, output_root: PathBuf, path: PathBuf) -> Self {
+        ResourceTask {
+            task_name,
+            input_root,
+            output_root,
+            path,
+        }
+    }
+
+    fn input_file(&self) -> PathBuf {
+        self.input_root.join(&self.path)
+    }
+
+    fn output_file(&self) -> PathBuf {
+        self.output_root.join(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use crate::testutil::{build, write};
+
+    /// Processes the resources of a project with the given settings,
+    /// returning the directory they got written to.
+    fn process(dir: &Path, settings: &str) -> PathBuf {
+        let build_root = dir.join("build");
+
+        write(&build_root.join("obuildroot"), "p\n");
+        write(
+            &build_root.join("p/obuild.toml"),
+            &format!(
+                "[project]\nname = \"p\"\nversion = \"1.2.0\"\nsource_root = \"../..\"\n\n{}",
+                settings
+            ),
+        );
+
+        assert!(build(&build_root));
+        build_root.join("p/resources")
+    }
+
+    #[test]
+    fn expands_properties() {
+        let dir = tempfile::tempdir().unwrap();
+        let resources = dir.path().join("resources");
+
+        write(
+            &resources.join("mod.json"),
+            "{\"id\": \"${name}\", \"version\": \"${version}\", \"x\": \"${other}\"}",
+        );
+        fs::write(resources.join("icon.png"), b"\x89PNG ${name} \xff").unwrap();
+
+        let output = process(dir.path(), "[resources]\nexpand = true\n");
+
+        assert_eq!(
+            fs::read_to_string(output.join("mod.json")).unwrap(),
+            "{\"id\": \"p\", \"version\": \"1.2.0\", \"x\": \"${other}\"}"
+        );
+
+        // not text, so there's nothing to expand
+        assert_eq!(
+            fs::read(output.join("icon.png")).unwrap(),
+            b"\x89PNG ${name} \xff"
+        );
+    }
+
+    #[test]
+    fn copies_files_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let resources = dir.path().join("resources");
+
+        write(
+            &resources.join("lang/en_us.json"),
+            "{\"${name}\": \"${version}\"}",
+        );
+
+        let output = process(dir.path(), "");
+
+        assert_eq!(
+            fs::read_to_string(output.join("lang/en_us.json")).unwrap(),
+            "{\"${name}\": \"${version}\"}"
+        );
+    }
+}
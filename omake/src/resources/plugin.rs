@@ -0,0 +1,274 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::{fs, io};
+
+use serde::Deserialize;
+
+use crate::plugin::Plugin;
+use crate::project::Project;
+use crate::resources::ResourceTask;
+use crate::sourceset::{self, SourceSet};
+use crate::task::{Context, PathDep, Task};
+use crate::Result;
+
+pub fn register(project: &mut Project) -> Result<()> {
+    #[derive(Deserialize)]
+    struct BuildSettings {
+        #[serde(default)]
+        resources: PluginSettings,
+    }
+
+    // `dirs` is read as part of the main source set
+    #[derive(Default, Deserialize)]
+    struct PluginSettings {
+        #[serde(default)]
+        expand: bool,
+    }
+
+    let BuildSettings {
+        resources: PluginSettings { expand },
+    } = BuildSettings::deserialize(project.build_file().clone())?;
+
+    let mut properties = BTreeMap::new();
+    properties.insert("name".to_string(), project.name().to_string());
+
+    if let Some(version) = project.version() {
+        properties.insert("version".to_string(), version.to_string());
+    }
+
+    let d = Arc::new(ResourcesData {
+        source_root: project.source_root().to_path_buf(),
+        build_root: project.build_root().to_path_buf(),
+        sets: sourceset::read(project)?,
+        expand,
+        properties,
+    });
+
+    let plugin = ResourcesPlugin { data: d.clone() };
+    let ext = ResourcesExtension { data: d };
+
+    project.extensions_mut().insert(ext);
+    project.register_plugin(plugin);
+
+    Ok(())
+}
+
+#[derive(Debug)]
+struct ResourcesData {
+    source_root: PathBuf,
+    build_root: PathBuf,
+    sets: Vec<SourceSet>,
+    /// Whether to replace `${...}` placeholders in resource files with the
+    /// values in `properties`.
+    expand: bool,
+    properties: BTreeMap<String, String>,
+}
+
+impl ResourcesData {
+    fn output_dir(&self, set: &SourceSet) -> PathBuf {
+        self.build_root.join(set.resources_output())
+    }
+}
+
+pub struct ResourcesPlugin {
+    data: Arc<ResourcesData>,
+}
+
+impl Plugin for ResourcesPlugin {
+    fn create_tasks(&self, _project: &Project) -> Result<Vec<Box<dyn Task>>> {
+        let data = &self.data;
+
+        let mut vec: Vec<Box<dyn Task>> = Vec::new();
+
+        for set in &data.sets {
+            let task_name = set.task_name("process", "Resources");
+            let output_dir = data.output_dir(set);
+
+            // a file in an earlier directory shadows one with the same path
+            // in a later one
+            let mut seen = HashSet::new();
+
+            for dir in set.resources() {
+                let input_dir = data.source_root.join(dir);
+                let dep = PathDep::new(input_dir.clone()).with_dir(true, "*");
+
+                for file in dep.paths() {
+                    let path = file.strip_prefix(&input_dir).unwrap().to_path_buf();
+
+                    if seen.insert(path.clone()) {
+                        vec.push(Box::new(ResourceTask::new(
+                            task_name.clone(),
+                            input_dir.clone(),
+                            output_dir.clone(),
+                            path,
+                        )));
+                    }
+                }
+            }
+        }
+
+        Ok(vec)
+    }
+
+    fn remove_stale_outputs(&self, project: &Project) -> io::Result<()> {
+        let ctx = Context::new(project);
+        let data = &self.data;
+
+        for set in &data.sets {
+            let task_name = set.task_name("process", "Resources");
+            let output_dir = data.output_dir(set);
+
+            let outputs: Vec<_> = PathDep::new(output_dir.clone())
+                .with_dir(true, "*")
+                .paths()
+                .map(Cow::into_owned)
+                .collect();
+
+            for output in outputs {
+                let path = output.strip_prefix(&output_dir).unwrap();
+                let exists = set
+                    .resources()
+                    .iter()
+                    .any(|el| data.source_root.join(el).join(path).is_file());
+
+                if exists {
+                    continue;
+                }
+
+                // Only remove files a resource task wrote, the output
+                // directory may have files from somewhere else. The input
+                // directory doesn't matter for the outputs.
+                let task = ResourceTask::new(
+                    task_name.clone(),
+                    PathBuf::new(),
+                    output_dir.clone(),
+                    path.to_path_buf(),
+                );
+
+                let recorded = match ctx.load_state(&task) {
+                    Some(state) => state.outputs().any(|el| el == output),
+                    None => false,
+                };
+
+                if !recorded {
+                    continue;
+                }
+
+                println!("removing deleted resource '{}'", path.display());
+                fs::remove_file(&output)?;
+
+                match fs::remove_file(task.state_path(&ctx)) {
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct ResourcesExtension {
+    data: Arc<ResourcesData>,
+}
+
+impl ResourcesExtension {
+    /// Returns the directory processed resources of the source set named
+    /// `set` get written to.
+    pub fn output_dir(&self, set: &str) -> Option<PathBuf> {
+        let set = self.data.sets.iter().find(|el| el.name() == set)?;
+        Some(self.data.output_dir(set))
+    }
+
+    pub fn expand(&self) -> bool {
+        self.data.expand
+    }
+
+    /// Returns the settings that affect the contents of processed resources.
+    pub fn fingerprint(&self) -> String {
+        if !self.data.expand {
+            return "copy".to_string();
+        }
+
+        let mut s = "expand\n".to_string();
+
+        for (key, value) in &self.data.properties {
+            s.push_str(&format!("{}={}\n", key, value));
+        }
+
+        s
+    }
+
+    /// Replaces every `${key}` in `text` with the value of the project
+    /// property `key`. Placeholders for unknown properties are left as they
+    /// are.
+    pub fn expand_properties(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find("${") {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let value = rest
+                .find('}')
+                .and_then(|end| Some((end, self.data.properties.get(&rest[2..end])?)));
+
+            match value {
+                Some((end, value)) => {
+                    out.push_str(value);
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    out.push_str("${");
+                    rest = &rest[2..];
+                }
+            }
+        }
+
+        out.push_str(rest);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::load_projects;
+    use crate::testutil::{build, write};
+
+    #[test]
+    fn removes_outputs_of_deleted_resources() {
+        let dir = tempfile::tempdir().unwrap();
+        let build_root = dir.path().join("build");
+        let resources = dir.path().join("resources");
+        let output = build_root.join("p/resources");
+
+        write(&build_root.join("obuildroot"), "p\n");
+        write(
+            &build_root.join("p/obuild.toml"),
+            "[project]\nname = \"p\"\nsource_root = \"../..\"\n",
+        );
+        write(&resources.join("a.txt"), "a");
+        write(&resources.join("sub/b.txt"), "b");
+
+        assert!(build(&build_root));
+        assert!(output.join("sub/b.txt").is_file());
+
+        // omake never wrote this one, so it has to stay
+        write(&output.join("c.txt"), "c");
+        fs::remove_file(resources.join("sub/b.txt")).unwrap();
+
+        for project in load_projects(&build_root).unwrap() {
+            project.remove_stale_outputs().unwrap();
+        }
+
+        assert!(output.join("a.txt").is_file());
+        assert!(!output.join("sub/b.txt").exists());
+        assert!(output.join("c.txt").is_file());
+    }
+}
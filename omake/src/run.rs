@@ -0,0 +1,134 @@
+use std::process::ExitStatus;
+
+use crate::java::plugin::{ClassPathEntry, JavaExtension};
+use crate::project::Project;
+use crate::task::{PathDep, Scheduler, TaskError, TaskNode};
+use crate::{build_matching, Error, Result, Selection};
+
+/// Builds everything the selected project needs at runtime, then runs its
+/// main class with `args`. Returns the program's exit status, or `None` if
+/// the build failed.
+pub fn run(
+    projects: &[Project],
+    selection: &Selection,
+    scheduler: &Scheduler,
+    args: &[String],
+) -> Result<Option<ExitStatus>> {
+    let candidates: Vec<_> = projects
+        .iter()
+        .filter(|el| selection.includes_project(el))
+        .filter_map(|el| {
+            let ext = el.extensions().get::<JavaExtension>()?;
+            ext.launch().map(|launch| (el, ext, launch))
+        })
+        .collect();
+
+    let (project, ext, launch) = match candidates.len() {
+        0 => return Err(Error::NoMainClass),
+        1 => candidates.into_iter().next().unwrap(),
+        _ => {
+            return Err(Error::AmbiguousMainClass(
+                candidates
+                    .iter()
+                    .map(|(el, _, _)| el.name().to_string())
+                    .collect(),
+            ))
+        }
+    };
+
+    // Only build the tasks producing something on the class path, running
+    // the tests isn't needed for this.
+    let inputs: Vec<_> = ext
+        .runtime_class_path()
+        .iter()
+        .map(ClassPathEntry::to_input)
+        .collect();
+
+    let built = build_matching(projects, scheduler, |node| on_class_path(node, &inputs))?;
+
+    if !built {
+        return Ok(None);
+    }
+
+    println!("running {} in project '{}'", launch.main_class(), project.name());
+
+    let status = launch.exec(args).map_err(|e| Error::Task {
+        project: project.name().to_string(),
+        task: "run".to_string(),
+        source: TaskError::Exec(e),
+    })?;
+
+    Ok(Some(status))
+}
+
+/// Returns whether the task of `node` produces something on the class path
+/// given by `inputs`.
+fn on_class_path(node: &TaskNode, inputs: &[PathDep]) -> bool {
+    let outputs = node.task().outputs(&node.context());
+
+    outputs
+        .iter()
+        .any(|output| inputs.iter().any(|input| output.overlaps(input)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::on_class_path;
+    use crate::java::plugin::{ClassPathEntry, JavaExtension};
+    use crate::testutil::write;
+    use crate::{load_projects, task_graph};
+
+    #[test]
+    fn only_builds_the_class_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let build_root = dir.path().join("build");
+
+        write(&build_root.join("obuildroot"), "lib\napp\n");
+        write(
+            &build_root.join("lib/obuild.toml"),
+            "[project]\nname = \"lib\"\nsource_root = \"../../lib\"\n\n[sets.test]\n",
+        );
+        write(
+            &build_root.join("app/obuild.toml"),
+            "[project]\nname = \"app\"\nsource_root = \"../../app\"\n\
+             dependencies = [\"lib\"]\n\n[java]\nmain_class = \"app.Main\"\n\n[sets.test]\n",
+        );
+
+        for project in &["lib", "app"] {
+            let root = dir.path().join(project);
+            write(
+                &root.join(format!("src/{}/Main.java", project)),
+                &format!("package {};\npublic class Main {{}}\n", project),
+            );
+            write(
+                &root.join(format!("test/{}/MainTest.java", project)),
+                &format!("package {};\npublic class MainTest {{}}\n", project),
+            );
+        }
+
+        let projects = load_projects(&build_root).unwrap();
+        let app = projects.iter().find(|el| el.name() == "app").unwrap();
+        let inputs: Vec<_> = app
+            .extensions()
+            .get::<JavaExtension>()
+            .unwrap()
+            .runtime_class_path()
+            .iter()
+            .map(ClassPathEntry::to_input)
+            .collect();
+
+        let graph = task_graph(&projects, |node| on_class_path(node, &inputs)).unwrap();
+        let mut tasks: Vec<_> = graph
+            .sorted()
+            .unwrap()
+            .into_iter()
+            .map(|el| graph[el].to_string())
+            .collect();
+        tasks.sort();
+
+        assert_eq!(
+            tasks,
+            ["app:compileJava:app/Main", "lib:compileJava:lib/Main"]
+        );
+    }
+}
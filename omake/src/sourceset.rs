@@ -0,0 +1,244 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::project::{normalize, Project};
+use crate::{Error, Result};
+
+/// The name of the source set the project's main code is in.
+pub const MAIN: &str = "main";
+
+/// The name of the source set the project's tests are in.
+pub const TEST: &str = "test";
+
+/// A named group of source files and resources that get built together, such
+/// as a project's main code or its tests.
+///
+/// Source sets are declared as `[sets.<name>]` tables in `obuild.toml`. The
+/// `main` set always exists, and takes its defaults from the `sources` and
+/// `output` keys of the `[java]` table and the `dirs` key of the
+/// `[resources]` table. Every other set additionally sees the classes of the
+/// `main` set.
+#[derive(Debug, Clone)]
+pub struct SourceSet {
+    name: String,
+    sources: Vec<PathBuf>,
+    resources: Vec<PathBuf>,
+    class_path: Vec<PathBuf>,
+    output: PathBuf,
+    resources_output: PathBuf,
+}
+
+impl SourceSet {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_main(&self) -> bool {
+        self.name == MAIN
+    }
+
+    /// Returns the source directories, relative to the source root.
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    /// Returns the resource directories, relative to the source root.
+    pub fn resources(&self) -> &[PathBuf] {
+        &self.resources
+    }
+
+    /// Returns the additional class path entries of this set, relative to the
+    /// source root.
+    pub fn class_path(&self) -> &[PathBuf] {
+        &self.class_path
+    }
+
+    /// Returns the directory compiled classes get written to, relative to the
+    /// build root.
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    /// Returns the directory processed resources get written to, relative
+    /// to the build root.
+    pub fn resources_output(&self) -> &Path {
+        &self.resources_output
+    }
+
+    /// Returns the name of the task doing `action` to the `target` of this
+    /// set, following Gradle's naming scheme: `compileJava` for the main set,
+    /// `compileTestJava` for the `test` set.
+    pub fn task_name(&self, action: &str, target: &str) -> String {
+        if self.is_main() {
+            return format!("{}{}", action, target);
+        }
+
+        let mut chars = self.name.chars();
+        let first = chars.next().map(|el| el.to_uppercase().collect::<String>());
+
+        format!(
+            "{}{}{}{}",
+            action,
+            first.unwrap_or_default(),
+            chars.as_str(),
+            target
+        )
+    }
+}
+
+/// Reads the source sets of `project` from its build file. The `main` set
+/// comes first, the others follow in the order they are declared in.
+pub fn read(project: &Project) -> Result<Vec<SourceSet>> {
+    #[derive(Deserialize)]
+    struct BuildSettings {
+        #[serde(default)]
+        java: JavaSettings,
+        #[serde(default)]
+        resources: ResourcesSettings,
+        #[serde(default)]
+        sets: toml::value::Table,
+    }
+
+    #[derive(Default, Deserialize)]
+    struct JavaSettings {
+        sources: Option<Vec<PathBuf>>,
+        output: Option<PathBuf>,
+    }
+
+    #[derive(Default, Deserialize)]
+    struct ResourcesSettings {
+        dirs: Option<Vec<PathBuf>>,
+    }
+
+    #[derive(Default, Deserialize)]
+    struct SetSettings {
+        sources: Option<Vec<PathBuf>>,
+        resources: Option<Vec<PathBuf>>,
+        #[serde(default)]
+        classpath: Vec<PathBuf>,
+        output: Option<PathBuf>,
+    }
+
+    let BuildSettings {
+        java,
+        resources,
+        mut sets,
+    } = BuildSettings::deserialize(project.build_file().clone())?;
+
+    let main = match sets.remove(MAIN) {
+        Some(v) => SetSettings::deserialize(v)?,
+        None => SetSettings::default(),
+    };
+
+    let mut vec = vec![SourceSet {
+        name: MAIN.to_string(),
+        sources: main
+            .sources
+            .or(java.sources)
+            .unwrap_or_else(|| vec![PathBuf::from("src")]),
+        resources: main
+            .resources
+            .or(resources.dirs)
+            .unwrap_or_else(|| vec![PathBuf::from("resources")]),
+        class_path: main.classpath,
+        output: main
+            .output
+            .or(java.output)
+            .unwrap_or_else(|| PathBuf::from("src")),
+        resources_output: PathBuf::from("resources"),
+    }];
+
+    for (name, v) in sets {
+        let settings = SetSettings::deserialize(v)?;
+
+        vec.push(SourceSet {
+            sources: settings
+                .sources
+                .unwrap_or_else(|| vec![PathBuf::from(&name)]),
+            resources: settings
+                .resources
+                .unwrap_or_else(|| vec![PathBuf::from(format!("{}-resources", name))]),
+            class_path: settings.classpath,
+            output: settings.output.unwrap_or_else(|| PathBuf::from(&name)),
+            resources_output: PathBuf::from(format!("{}-resources", name)),
+            name,
+        });
+    }
+
+    // The plugins remove what they wrote for deleted sources from their
+    // output directories, so they can't share any. Compare them as
+    // the plugins see them, so that different ways of writing the same
+    // directory don't get past this.
+    let outputs: Vec<_> = vec
+        .iter()
+        .flat_map(|el| [&el.output, &el.resources_output])
+        .map(|el| normalize(&project.build_root().join(el)))
+        .collect();
+
+    for (idx, first) in outputs.iter().enumerate() {
+        let overlapping = outputs[idx + 1..]
+            .iter()
+            .find(|el| first.starts_with(el) || el.starts_with(first));
+
+        if let Some(second) = overlapping {
+            return Err(Error::OverlappingOutputs {
+                first: first.clone(),
+                second: second.clone(),
+            });
+        }
+    }
+
+    Ok(vec)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::project::load_project;
+    use crate::Error;
+
+    #[test]
+    fn rejects_overlapping_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let read = |sets: &str| {
+            let content = format!("[project]\nname = \"p\"\nsource_root = \".\"\n\n{}", sets);
+            fs::write(dir.path().join("obuild.toml"), content).unwrap();
+            super::read(&load_project(dir.path()).unwrap())
+        };
+
+        assert_eq!(read("[sets.test]\n").unwrap().len(), 2);
+
+        // the set's classes go where the main set's do
+        assert!(matches!(
+            read("[sets.src]\n"),
+            Err(Error::OverlappingOutputs { .. })
+        ));
+
+        // the set's classes go where the main set's resources do
+        assert!(matches!(
+            read("[sets.resources]\n"),
+            Err(Error::OverlappingOutputs { .. })
+        ));
+
+        assert!(matches!(
+            read("[sets.test]\noutput = \"src/test\"\n"),
+            Err(Error::OverlappingOutputs { .. })
+        ));
+
+        // the same directories, written differently
+        let src = dir.path().join("src");
+        let outputs = [".", "./src", "a/../src", &src.to_string_lossy()];
+
+        for output in outputs {
+            let sets = format!("[sets.test]\noutput = \"{}\"\n", output);
+            let result = read(&sets);
+            assert!(
+                matches!(result, Err(Error::OverlappingOutputs { .. })),
+                "{}",
+                output
+            );
+        }
+    }
+}
@@ -1,18 +1,35 @@
-use std::cmp::{max, min};
 use std::fmt::Debug;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::{fs, io};
 
+use log::warn;
+use thiserror::Error;
+
+pub use graph::*;
 pub use pathdep::*;
+pub use scheduler::*;
+pub use stale::*;
+pub use state::*;
 
 use crate::project::Project;
 
+mod glob;
+mod graph;
 mod pathdep;
+mod scheduler;
+mod stale;
+mod state;
+
+pub trait Task: Debug + Send + Sync {
+    /// Returns the name of this task, used to select it on the command line.
+    /// Names of related tasks share a common prefix separated by a `:`, for
+    /// example `compileJava:net/example/Main`.
+    fn name(&self) -> String;
 
-pub trait Task: Debug {
-    fn make(&self, ctx: &Context) -> Result<(), String>;
+    fn make(&self, ctx: &Context) -> Result<(), TaskError>;
 
     fn inputs(&self, ctx: &Context) -> Vec<PathDep>;
 
@@ -20,9 +37,72 @@ pub trait Task: Debug {
 
     /// Gets called to check if this task is out of date based on all
     /// dependencies returned from [`Task::inputs()`] that are not generated by another
-    /// task. Usually, you want to just call [`input_changed()`] or a variant
-    /// thereof here.
-    fn needs_exec(&self, ctx: &Context) -> bool;
+    /// task, returning why it is, or `None` if it isn't. Usually, you want to
+    /// just call [`input_changed()`] or a variant thereof here. This is only
+    /// used for tasks that have no recorded state yet, see
+    /// [`Context::stale_reason()`].
+    fn needs_exec(&self, ctx: &Context) -> Option<StaleReason>;
+
+    /// Returns files this task reads that don't count as inputs when
+    /// deciding whether it is out of date, for example because only the part
+    /// of them covered by the fingerprint matters. Tasks producing them still
+    /// run before this one.
+    fn soft_inputs(&self, _ctx: &Context) -> Vec<PathDep> {
+        Vec::new()
+    }
+
+    /// Returns a description of everything besides the input files that
+    /// affects what this task produces, such as the command line it runs.
+    /// The task is out of date when this differs from the last time it ran.
+    fn fingerprint(&self, _ctx: &Context) -> Option<String> {
+        None
+    }
+
+    /// Returns the files this task produced, which get removed when cleaning
+    /// the project. Only the files matched by file outputs are known by
+    /// default, since a directory output may match files that came from
+    /// somewhere else.
+    fn produced_files(&self, ctx: &Context) -> Vec<PathBuf> {
+        self.outputs(ctx)
+            .into_iter()
+            .filter(|el| el.is_file())
+            .map(|el| el.base().to_path_buf())
+            .collect()
+    }
+
+    /// Returns the commands [`Task::make()`] would run, one per line, for
+    /// printing them in a dry run. Tasks that don't run any describe what
+    /// they'd do instead.
+    fn commands(&self, _ctx: &Context) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Returns the path of the file the task's state gets recorded in.
+    fn state_path(&self, ctx: &Context) -> PathBuf {
+        default_state_path(ctx.project().build_root(), &self.name())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TaskError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Exec(#[from] ExecError),
+    #[error("task panicked")]
+    Panicked,
+}
+
+#[derive(Debug, Error)]
+pub enum ExecError {
+    #[error("process exited with {0}")]
+    ExitStatus(ExitStatus),
+    #[error("task failed")]
+    Failed,
+    #[error("JVM error: {0}")]
+    Jvm(#[from] jvmapi::jvm::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
 }
 
 pub struct Context<'a> {
@@ -34,8 +114,8 @@ impl<'a> Context<'a> {
         Context { project }
     }
 
-    pub fn create_temp_dir(&self) -> TempDir {
-        TempDir::create_in(self.project.build_root()).unwrap()
+    pub fn create_temp_dir(&self) -> io::Result<TempDir> {
+        TempDir::create_in(&omake_dir(self.project.build_root()).join("tmp"))
     }
 
     pub fn get_extension<T: 'static>(&self) -> Option<&T> {
@@ -45,6 +125,60 @@ impl<'a> Context<'a> {
     pub fn project(&self) -> &Project {
         self.project
     }
+
+    /// Returns the state recorded for `task` when it last ran.
+    pub fn load_state(&self, task: &dyn Task) -> Option<TaskState> {
+        let path = task.state_path(self);
+
+        match TaskState::load(&path) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("failed to read task state '{}': {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Returns whether `task` needs to run.
+    pub fn is_stale(&self, task: &dyn Task) -> bool {
+        self.stale_reason(task).is_some()
+    }
+
+    /// Returns why `task` needs to run, or `None` if it is up to date. If the
+    /// task has run before, this compares its fingerprint and the contents of
+    /// its inputs and outputs with the recorded ones, otherwise it falls back
+    /// to [`Task::needs_exec()`].
+    pub fn stale_reason(&self, task: &dyn Task) -> Option<StaleReason> {
+        match self.load_state(task) {
+            None => task.needs_exec(self),
+            Some(state) => state
+                .stale_reason(
+                    task.fingerprint(self).as_deref(),
+                    &task.inputs(self),
+                    &task.outputs(self),
+                )
+                .unwrap_or_else(|e| Some(e.into())),
+        }
+    }
+
+    /// Records the current state of the inputs and outputs of `task`, after
+    /// it ran or was found to be up to date.
+    pub fn record_state(&self, task: &dyn Task) -> io::Result<()> {
+        let previous = self.load_state(task);
+        let state = TaskState::capture(
+            task.fingerprint(self).as_deref(),
+            &task.inputs(self),
+            &task.outputs(self),
+            previous.as_ref(),
+        )?;
+
+        // don't rewrite the file if nothing changed
+        if previous.as_ref() != Some(&state) {
+            state.save(&task.state_path(self))?;
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -97,24 +231,42 @@ impl Drop for TempDir {
     }
 }
 
-pub fn input_changed_multi<P1, P2>(inputs: &[P1], outputs: &[P2]) -> io::Result<bool>
+/// Returns the directory omake keeps its own files in, such as the state of
+/// tasks and temporary directories. Only omake writes to it.
+pub fn omake_dir(build_root: &Path) -> PathBuf {
+    build_root.join(".omake")
+}
+
+/// Compares the modification times of `inputs` and `outputs`, returning why
+/// the outputs are out of date, or `None` if they aren't: a missing output,
+/// or an input newer than the oldest output.
+pub fn input_changed_multi<P1, P2>(inputs: &[P1], outputs: &[P2]) -> io::Result<Option<StaleReason>>
 where
     P1: PathDepLike,
     P2: PathDepLike,
 {
-    let mut mtime_inputs = None;
-    let mut mtime_outputs = None;
+    let mut newest_input = None;
+    let mut oldest_output = None;
 
     for input in inputs {
         let input = input.to_path_dep();
 
         for path in input.paths() {
-            let metadata = fs::metadata(path)?;
+            let metadata = match fs::metadata(&path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    if input.optional() {
+                        continue;
+                    } else {
+                        return Ok(Some(StaleReason::InputMissing(path.into_owned())));
+                    }
+                }
+                r => r?,
+            };
             let mtime = metadata.modified()?;
 
-            mtime_inputs = match mtime_inputs {
-                None => Some(mtime),
-                Some(old_mtime) => Some(max(old_mtime, mtime)),
+            newest_input = match newest_input {
+                Some((old_mtime, _)) if old_mtime >= mtime => newest_input,
+                _ => Some((mtime, path.into_owned())),
             }
         }
     }
@@ -123,24 +275,60 @@ where
         let output = output.to_path_dep();
 
         for path in output.paths() {
-            let metadata = fs::metadata(path)?;
+            let metadata = match fs::metadata(&path) {
+                // a missing output always needs to be rebuilt
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    if output.optional() {
+                        continue;
+                    } else {
+                        return Ok(Some(StaleReason::OutputMissing(path.into_owned())));
+                    }
+                }
+                r => r?,
+            };
             let mtime = metadata.modified()?;
 
-            mtime_outputs = match mtime_outputs {
-                None => Some(mtime),
-                Some(old_mtime) => Some(min(old_mtime, mtime)),
+            oldest_output = match oldest_output {
+                Some((old_mtime, _)) if old_mtime <= mtime => oldest_output,
+                _ => Some((mtime, path.into_owned())),
             }
         }
     }
 
-    match (mtime_inputs, mtime_outputs) {
-        (Some(mtime_inputs), Some(mtime_outputs)) => Ok(mtime_inputs > mtime_outputs),
-        _ => Ok(true),
+    match (newest_input, oldest_output) {
+        (Some((input_mtime, input)), Some((output_mtime, output))) => {
+            if input_mtime > output_mtime {
+                Ok(Some(StaleReason::InputNewer { input, output }))
+            } else {
+                Ok(None)
+            }
+        }
+        (None, _) => Ok(Some(StaleReason::NoInputs)),
+        (_, None) => Ok(Some(StaleReason::NoOutputs)),
     }
 }
 
-pub fn input_changed(input: &Path, output: &Path) -> io::Result<bool> {
-    let input_meta = fs::metadata(input)?;
-    let output_meta = fs::metadata(output)?;
-    Ok(input_meta.modified()? > output_meta.modified()?)
+/// Like [`input_changed_multi()`], for a single input and output file.
+pub fn input_changed(input: &Path, output: &Path) -> io::Result<Option<StaleReason>> {
+    let input_meta = match fs::metadata(input) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(Some(StaleReason::InputMissing(input.to_path_buf())))
+        }
+        r => r?,
+    };
+    let output_meta = match fs::metadata(output) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(Some(StaleReason::OutputMissing(output.to_path_buf())))
+        }
+        r => r?,
+    };
+
+    if input_meta.modified()? > output_meta.modified()? {
+        Ok(Some(StaleReason::InputNewer {
+            input: input.to_path_buf(),
+            output: output.to_path_buf(),
+        }))
+    } else {
+        Ok(None)
+    }
 }
@@ -0,0 +1,288 @@
+use std::path::{Component, Path};
+
+/// A shell-style pattern matching paths relative to some directory.
+///
+/// - `*` matches any sequence of characters except `/`
+/// - `?` matches any single character except `/`
+/// - `**` as a whole path component matches any number of directories,
+///   including none
+/// - `[abc]`, `[a-z]` match one of the given characters, `[!abc]` or
+///   `[^abc]` one that isn't
+/// - `\` matches the next character literally
+///
+/// Like in `.gitignore`, a pattern without a `/` matches files with that name
+/// in any directory, so `*.class` matches both `A.class` and `a/B.class`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Glob {
+    pattern: String,
+    components: Vec<GlobComponent>,
+    /// Whether the pattern only applies to file names.
+    name_only: bool,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum GlobComponent {
+    /// `**`, any number of path components.
+    Recursive,
+    Segment(Vec<Token>),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Token {
+    Char(char),
+    Any,
+    Star,
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        let components: Vec<_> = pattern
+            .split('/')
+            .filter(|el| !el.is_empty())
+            .map(|el| match el {
+                "**" => GlobComponent::Recursive,
+                _ => GlobComponent::Segment(parse_segment(el)),
+            })
+            .collect();
+
+        let name_only = !pattern.contains('/') && components != [GlobComponent::Recursive];
+
+        Glob {
+            pattern: pattern.to_string(),
+            components,
+            name_only,
+        }
+    }
+
+    /// Returns the pattern this was created from.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns whether the pattern matches `path`, which is relative to the
+    /// directory the pattern applies to. Paths that aren't valid UTF-8 never
+    /// match.
+    pub fn matches(&self, path: &Path) -> bool {
+        let mut parts = Vec::new();
+
+        for c in path.components() {
+            match c {
+                Component::Normal(s) => match s.to_str() {
+                    Some(s) => parts.push(s),
+                    None => return false,
+                },
+                Component::CurDir => {}
+                _ => return false,
+            }
+        }
+
+        if self.name_only {
+            match parts.last() {
+                Some(name) => match_components(&self.components, &[name]),
+                None => false,
+            }
+        } else {
+            match_components(&self.components, &parts)
+        }
+    }
+}
+
+fn parse_segment(s: &str) -> Vec<Token> {
+    let chars: Vec<_> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        let token = match chars[idx] {
+            '*' => {
+                // consecutive stars are the same as one inside a component
+                while chars.get(idx + 1) == Some(&'*') {
+                    idx += 1;
+                }
+
+                Token::Star
+            }
+            '?' => Token::Any,
+            '\\' if idx + 1 < chars.len() => {
+                idx += 1;
+                Token::Char(chars[idx])
+            }
+            '[' => match parse_class(&chars[idx + 1..]) {
+                Some((token, len)) => {
+                    idx += len;
+                    token
+                }
+                // an unclosed bracket is just a bracket
+                None => Token::Char('['),
+            },
+            c => Token::Char(c),
+        };
+
+        tokens.push(token);
+        idx += 1;
+    }
+
+    tokens
+}
+
+/// Parses a character class starting after the `[`, returning it and the
+/// number of characters it took up including the closing `]`.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut idx = 0;
+    let negated = matches!(chars.first(), Some('!') | Some('^'));
+
+    if negated {
+        idx += 1;
+    }
+
+    let mut ranges = Vec::new();
+
+    // a `]` right at the start is part of the class
+    let mut first = true;
+
+    loop {
+        let c = *chars.get(idx)?;
+
+        if c == ']' && !first {
+            return Some((Token::Class { negated, ranges }, idx + 1));
+        }
+
+        first = false;
+
+        let range_end = chars.get(idx + 2).filter(|el| **el != ']');
+
+        if chars.get(idx + 1) == Some(&'-') && range_end.is_some() {
+            ranges.push((c, chars[idx + 2]));
+            idx += 3;
+        } else {
+            ranges.push((c, c));
+            idx += 1;
+        }
+    }
+}
+
+fn match_components(pattern: &[GlobComponent], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((GlobComponent::Recursive, rest)) => {
+            (0..=path.len()).any(|idx| match_components(rest, &path[idx..]))
+        }
+        Some((GlobComponent::Segment(tokens), rest)) => match path.split_first() {
+            Some((first, path_rest)) => {
+                let chars: Vec<_> = first.chars().collect();
+                match_segment(tokens, &chars) && match_components(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_segment(tokens: &[Token], s: &[char]) -> bool {
+    match tokens.split_first() {
+        None => s.is_empty(),
+        Some((Token::Star, rest)) => (0..=s.len()).any(|idx| match_segment(rest, &s[idx..])),
+        Some((token, rest)) => match s.split_first() {
+            Some((c, s_rest)) => token_matches(token, *c) && match_segment(rest, s_rest),
+            None => false,
+        },
+    }
+}
+
+fn token_matches(token: &Token, c: char) -> bool {
+    match token {
+        Token::Char(expected) => *expected == c,
+        Token::Any => true,
+        Token::Star => unreachable!(),
+        Token::Class { negated, ranges } => {
+            ranges.iter().any(|(from, to)| (*from..=*to).contains(&c)) != *negated
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Glob;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        Glob::new(pattern).matches(Path::new(path))
+    }
+
+    #[test]
+    fn star() {
+        assert!(matches("*.class", "A.class"));
+        assert!(matches("*.class", ".class"));
+        assert!(matches("A*B", "AxyzB"));
+        assert!(!matches("*.class", "A.java"));
+        assert!(!matches("*.class", "A.class.bak"));
+        assert!(matches("**.class", "A.class"));
+    }
+
+    #[test]
+    fn question_mark() {
+        assert!(matches("?.class", "A.class"));
+        assert!(!matches("?.class", "AB.class"));
+        assert!(!matches("?.class", ".class"));
+    }
+
+    #[test]
+    fn recursive() {
+        assert!(matches("a/**/b", "a/b"));
+        assert!(matches("a/**/b", "a/x/b"));
+        assert!(matches("a/**/b", "a/x/y/b"));
+        assert!(!matches("a/**/b", "a/x/c"));
+        assert!(matches("**", "a/b/c"));
+        assert!(matches("**/*.java", "A.java"));
+        assert!(matches("a/**", "a/b/c"));
+        assert!(!matches("a/**", "b/c"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("[abc].txt", "b.txt"));
+        assert!(!matches("[abc].txt", "d.txt"));
+        assert!(matches("[a-c].txt", "b.txt"));
+        assert!(!matches("[a-c].txt", "B.txt"));
+        assert!(matches("[!a-c].txt", "d.txt"));
+        assert!(matches("[^a-c].txt", "d.txt"));
+        assert!(!matches("[!a-c].txt", "a.txt"));
+
+        // a leading `]` is part of the class, a trailing `-` isn't a range
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[a-]", "-"));
+
+        // an unclosed bracket is matched literally
+        assert!(matches("[a", "[a"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches("\\*.txt", "*.txt"));
+        assert!(!matches("\\*.txt", "a.txt"));
+        assert!(matches("\\[a].txt", "[a].txt"));
+        assert!(matches("a\\?", "a?"));
+        assert!(!matches("a\\?", "ab"));
+    }
+
+    #[test]
+    fn segment_boundaries() {
+        // wildcards don't match across a `/`
+        assert!(!matches("a*b/c", "a/b/c"));
+        assert!(!matches("a?b", "a/b"));
+        assert!(!matches("a/*", "a/b/c"));
+        assert!(matches("a/*/c", "a/b/c"));
+
+        // a pattern without a `/` matches the file name in any directory,
+        // one with a `/` the whole path
+        assert!(matches("*.class", "a/b/C.class"));
+        assert!(!matches("b/*.class", "a/b/C.class"));
+        assert!(matches("a/b/*.class", "a/b/C.class"));
+        assert!(matches("a/b/*.class", "./a/b/C.class"));
+        assert!(!matches("a/*.class", "../a/C.class"));
+    }
+}
@@ -0,0 +1,514 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::ops::Index;
+
+use petgraph::algo::{tarjan_scc, toposort};
+use petgraph::graph::{DiGraph, NodeIndex};
+use petgraph::visit::{Dfs, Reversed};
+use petgraph::Direction;
+use thiserror::Error;
+
+use crate::project::Project;
+use crate::task::{Context, PathDep, Task};
+use crate::Result;
+
+/// The tasks of a build, linked by the files they produce and consume. An edge
+/// from task A to task B means that A outputs a file that B takes as input,
+/// so A has to run before B.
+pub struct TaskGraph<'a> {
+    graph: DiGraph<TaskNode<'a>, ()>,
+}
+
+pub struct TaskNode<'a> {
+    project: &'a Project,
+    task: Box<dyn Task>,
+    keep: bool,
+}
+
+impl<'a> TaskNode<'a> {
+    pub fn project(&self) -> &'a Project {
+        self.project
+    }
+
+    pub fn task(&self) -> &dyn Task {
+        &*self.task
+    }
+
+    pub fn context(&self) -> Context<'a> {
+        Context::new(self.project)
+    }
+}
+
+impl Display for TaskNode<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.project.name(), self.task.name())
+    }
+}
+
+impl<'a> TaskGraph<'a> {
+    pub fn new() -> Self {
+        TaskGraph {
+            graph: DiGraph::new(),
+        }
+    }
+
+    /// Adds all tasks created by the plugins of `project` to the graph. Call
+    /// [`TaskGraph::link()`] after adding all projects to compute the
+    /// dependencies between them.
+    pub fn add_project(&mut self, project: &'a Project) -> Result<()> {
+        for task in project.create_tasks()? {
+            self.graph.add_node(TaskNode {
+                project,
+                task,
+                keep: true,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Links tasks together by matching the outputs of each task against the
+    /// inputs and soft inputs of every other task. Additionally, every task of
+    /// a project runs after the tasks of the projects it depends on whose
+    /// outputs it uses, such as the classes on its class path.
+    pub fn link(&mut self) {
+        self.graph.clear_edges();
+
+        let deps: Vec<_> = self
+            .graph
+            .node_indices()
+            .map(|idx| {
+                let node = &self.graph[idx];
+                let ctx = node.context();
+                let mut inputs = node.task.inputs(&ctx);
+                inputs.extend(node.task.soft_inputs(&ctx));
+                (node.project, inputs, node.task.outputs(&ctx))
+            })
+            .collect();
+
+        // the tasks of each project and everything they take as input
+        let mut projects: Vec<(&Project, Vec<usize>, Vec<PathDep>)> = Vec::new();
+
+        for (idx, (project, inputs, _)) in deps.iter().enumerate() {
+            let pos = match projects.iter().position(|el| std::ptr::eq(el.0, *project)) {
+                Some(pos) => pos,
+                None => {
+                    projects.push((project, Vec::new(), Vec::new()));
+                    projects.len() - 1
+                }
+            };
+
+            let (_, tasks, all_inputs) = &mut projects[pos];
+            tasks.push(idx);
+            all_inputs.extend(inputs.iter().cloned());
+        }
+
+        let uses = |outputs: &[PathDep], inputs: &[PathDep]| {
+            outputs
+                .iter()
+                .any(|output| inputs.iter().any(|input| output.overlaps(input)))
+        };
+
+        for (from, (from_project, _, outputs)) in deps.iter().enumerate() {
+            let mut targets = BTreeSet::new();
+
+            for (to, (_, inputs, _)) in deps.iter().enumerate() {
+                if from != to && uses(outputs, inputs) {
+                    targets.insert(to);
+                }
+            }
+
+            let dependents = projects.iter().filter(|(project, _, _)| {
+                project
+                    .dependencies()
+                    .iter()
+                    .any(|el| el == from_project.name())
+            });
+
+            for (_, tasks, inputs) in dependents {
+                if uses(outputs, inputs) {
+                    targets.extend(tasks.iter().filter(|&&to| to != from));
+                }
+            }
+
+            for to in targets {
+                self.graph
+                    .add_edge(NodeIndex::new(from), NodeIndex::new(to), ());
+            }
+        }
+    }
+
+    /// Removes every task that is neither selected by `pred` nor a dependency
+    /// of a selected task.
+    pub fn select<F>(&mut self, mut pred: F)
+    where
+        F: FnMut(&TaskNode) -> bool,
+    {
+        let selected: Vec<_> = self
+            .graph
+            .node_indices()
+            .filter(|&idx| pred(&self.graph[idx]))
+            .collect();
+
+        let mut keep = vec![false; self.graph.node_count()];
+        let reversed = Reversed(&self.graph);
+
+        for start in selected {
+            let mut dfs = Dfs::new(reversed, start);
+
+            while let Some(idx) = dfs.next(reversed) {
+                keep[idx.index()] = true;
+            }
+        }
+
+        // mark the nodes first since retain_nodes shifts around the indices
+        for idx in self.graph.node_indices() {
+            self.graph[idx].keep = keep[idx.index()];
+        }
+
+        self.graph.retain_nodes(|g, idx| g[idx].keep);
+    }
+
+    /// Returns the tasks in the order they need to be executed in, or an error
+    /// if the dependencies between the tasks contain a cycle.
+    pub fn sorted(&self) -> Result<Vec<NodeIndex>, CycleError> {
+        toposort(&self.graph, None).map_err(|_| self.find_cycle())
+    }
+
+    /// Returns the tasks that have to run directly before the task at `idx`.
+    pub fn dependencies(&self, idx: NodeIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.graph.neighbors_directed(idx, Direction::Incoming)
+    }
+
+    /// Returns the tasks that directly depend on the task at `idx`.
+    pub fn dependents(&self, idx: NodeIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.graph.neighbors_directed(idx, Direction::Outgoing)
+    }
+
+    pub fn len(&self) -> usize {
+        self.graph.node_count()
+    }
+
+    /// Returns the graph in Graphviz DOT format, with the tasks of each
+    /// project grouped in a cluster.
+    pub fn to_dot(&self) -> String {
+        let mut s = "digraph tasks {\n    rankdir=LR;\n    node [shape=box];\n".to_string();
+        let mut projects: Vec<&Project> = Vec::new();
+
+        for idx in self.graph.node_indices() {
+            let project = self.graph[idx].project;
+
+            if !projects.iter().any(|el| std::ptr::eq(*el, project)) {
+                projects.push(project);
+            }
+        }
+
+        for (cluster, project) in projects.iter().enumerate() {
+            s.push_str(&format!("\n    subgraph cluster_{} {{\n", cluster));
+            s.push_str(&format!("        label={};\n", dot_string(project.name())));
+
+            let nodes = self
+                .graph
+                .node_indices()
+                .filter(|&idx| std::ptr::eq(self.graph[idx].project, *project));
+
+            for idx in nodes {
+                let label = dot_string(&self.graph[idx].task.name());
+                s.push_str(&format!("        n{} [label={}];\n", idx.index(), label));
+            }
+
+            s.push_str("    }\n");
+        }
+
+        if self.graph.edge_count() > 0 {
+            s.push('\n');
+        }
+
+        for edge in self.graph.raw_edges() {
+            s.push_str(&format!(
+                "    n{} -> n{};\n",
+                edge.source().index(),
+                edge.target().index()
+            ));
+        }
+
+        s.push_str("}\n");
+        s
+    }
+
+    fn find_cycle(&self) -> CycleError {
+        let scc = tarjan_scc(&self.graph)
+            .into_iter()
+            .find(|el| el.len() > 1)
+            .expect("toposort found a cycle, but there is no strongly connected component");
+
+        // Walk along the edges inside the strongly connected component until we
+        // arrive at a task we've already seen, that's our cycle.
+        let mut path = vec![scc[0]];
+
+        loop {
+            let last = *path.last().unwrap();
+            let next = self
+                .dependents(last)
+                .find(|el| scc.contains(el))
+                .unwrap();
+
+            if let Some(pos) = path.iter().position(|&el| el == next) {
+                let tasks = path[pos..]
+                    .iter()
+                    .chain(Some(&next))
+                    .map(|&el| self.graph[el].to_string())
+                    .collect();
+
+                break CycleError { tasks };
+            }
+
+            path.push(next);
+        }
+    }
+}
+
+impl<'a> Index<NodeIndex> for TaskGraph<'a> {
+    type Output = TaskNode<'a>;
+
+    fn index(&self, index: NodeIndex) -> &Self::Output {
+        &self.graph[index]
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("dependency cycle between tasks: {}", .tasks.join(" -> "))]
+pub struct CycleError {
+    tasks: Vec<String>,
+}
+
+/// Quotes `s` as a DOT string.
+fn dot_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::plugin::Plugin;
+    use crate::project::{load_project, Project};
+    use crate::task::{Context, PathDep, StaleReason, Task, TaskError, TaskGraph};
+    use crate::Result;
+
+    /// The name, input and output of a task. The files are relative to the
+    /// directory the projects are in.
+    type Spec = (&'static str, &'static str, &'static str);
+
+    #[derive(Debug)]
+    struct FileTask {
+        name: &'static str,
+        input: &'static str,
+        output: &'static str,
+    }
+
+    impl Task for FileTask {
+        fn name(&self) -> String {
+            self.name.to_string()
+        }
+
+        fn make(&self, _ctx: &Context) -> Result<(), TaskError> {
+            Ok(())
+        }
+
+        fn inputs(&self, ctx: &Context) -> Vec<PathDep> {
+            let dir = ctx.project().build_root().parent().unwrap();
+            vec![PathDep::new(dir.join(self.input))]
+        }
+
+        fn outputs(&self, ctx: &Context) -> Vec<PathDep> {
+            let dir = ctx.project().build_root().parent().unwrap();
+            vec![PathDep::new(dir.join(self.output))]
+        }
+
+        fn needs_exec(&self, _ctx: &Context) -> Option<StaleReason> {
+            Some(StaleReason::NeverSucceeded)
+        }
+    }
+
+    struct FilePlugin(&'static [Spec]);
+
+    impl Plugin for FilePlugin {
+        fn create_tasks(&self, _project: &Project) -> Result<Vec<Box<dyn Task>>> {
+            Ok(self
+                .0
+                .iter()
+                .map(|&(name, input, output)| {
+                    Box::new(FileTask {
+                        name,
+                        input,
+                        output,
+                    }) as Box<dyn Task>
+                })
+                .collect())
+        }
+    }
+
+    /// Creates the project `name` in a directory of the same name in `dir`.
+    fn project(dir: &Path, name: &str, dependencies: &[&str], tasks: &'static [Spec]) -> Project {
+        let dir = dir.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("obuild.toml"),
+            format!(
+                "[project]\nname = \"{}\"\nsource_root = \".\"\ndependencies = {:?}\n",
+                name, dependencies
+            ),
+        )
+        .unwrap();
+
+        let mut project = load_project(&dir).unwrap();
+        project.register_plugin(FilePlugin(tasks));
+        project
+    }
+
+    #[test]
+    fn sorts_by_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = project(
+            dir.path(),
+            "p",
+            &[],
+            &[("b", "x", "y"), ("a", "w", "x"), ("c", "y", "z")],
+        );
+
+        let mut graph = TaskGraph::new();
+        graph.add_project(&project).unwrap();
+        graph.link();
+
+        let order: Vec<_> = graph
+            .sorted()
+            .unwrap()
+            .into_iter()
+            .map(|el| graph[el].task().name())
+            .collect();
+
+        assert_eq!(order, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn reports_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = project(
+            dir.path(),
+            "p",
+            &[],
+            &[
+                ("a", "x", "y"),
+                ("b", "y", "z"),
+                ("c", "z", "x"),
+                ("d", "y", "w"),
+            ],
+        );
+
+        let mut graph = TaskGraph::new();
+        graph.add_project(&project).unwrap();
+        graph.link();
+
+        let err = graph.sorted().unwrap_err();
+
+        // the cycle can start at any of its tasks, but it has to end where
+        // it started, and contain each of them once
+        let mut tasks = err.tasks.clone();
+        assert_eq!(tasks.first(), tasks.last());
+        tasks.pop();
+        tasks.sort();
+        assert_eq!(tasks, ["p:a", "p:b", "p:c"]);
+        assert!(err
+            .to_string()
+            .starts_with("dependency cycle between tasks: "));
+    }
+
+    #[test]
+    fn links_dependencies_by_used_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib = project(
+            dir.path(),
+            "lib",
+            &[],
+            &[
+                ("compile", "lib/src", "lib/classes"),
+                ("test", "lib/classes", "lib/reports"),
+            ],
+        );
+        let app = project(
+            dir.path(),
+            "app",
+            &["lib"],
+            &[
+                ("compile", "lib/classes", "app/classes"),
+                ("jar", "app/classes", "app/app.jar"),
+            ],
+        );
+
+        let mut graph = TaskGraph::new();
+        graph.add_project(&lib).unwrap();
+        graph.add_project(&app).unwrap();
+        graph.link();
+
+        let mut edges: Vec<_> = graph
+            .graph
+            .raw_edges()
+            .iter()
+            .map(|el| {
+                let source = &graph.graph[el.source()];
+                let target = &graph.graph[el.target()];
+                (source.to_string(), target.to_string())
+            })
+            .collect();
+        edges.sort();
+
+        // the app uses the library's classes, but not its test reports
+        let expected = [
+            ("app:compile", "app:jar"),
+            ("lib:compile", "app:compile"),
+            ("lib:compile", "app:jar"),
+            ("lib:compile", "lib:test"),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(a, b)| (a.to_string(), b.to_string()))
+            .collect();
+        assert_eq!(edges, expected);
+    }
+
+    #[test]
+    fn writes_dot() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = project(
+            dir.path(),
+            "p",
+            &[],
+            &[("a", "w", "x"), ("say \"b\"", "x", "y")],
+        );
+
+        let mut graph = TaskGraph::new();
+        graph.add_project(&project).unwrap();
+        graph.link();
+
+        let expected = [
+            "digraph tasks {",
+            "    rankdir=LR;",
+            "    node [shape=box];",
+            "",
+            "    subgraph cluster_0 {",
+            "        label=\"p\";",
+            "        n0 [label=\"a\"];",
+            "        n1 [label=\"say \\\"b\\\"\"];",
+            "    }",
+            "",
+            "    n0 -> n1;",
+            "}",
+            "",
+        ];
+
+        assert_eq!(graph.to_dot(), expected.join("\n"));
+    }
+}
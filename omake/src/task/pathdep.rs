@@ -1,5 +1,5 @@
 use std::borrow::Cow;
-use std::ffi::{OsStr, OsString};
+use std::fmt::{self, Display, Formatter};
 use std::fs;
 use std::fs::ReadDir;
 use std::io::ErrorKind;
@@ -7,6 +7,8 @@ use std::path::{Path, PathBuf};
 
 use log::error;
 
+use crate::task::glob::Glob;
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct PathDep {
     base: PathBuf,
@@ -17,7 +19,11 @@ pub struct PathDep {
 #[derive(Debug, Clone, Eq, PartialEq)]
 enum DepType {
     File,
-    Dir { recursive: bool, pattern: OsString },
+    Dir {
+        recursive: bool,
+        include: Vec<Glob>,
+        exclude: Vec<Glob>,
+    },
 }
 
 impl PathDep {
@@ -34,21 +40,115 @@ impl PathDep {
         self
     }
 
-    pub fn with_dir<S: AsRef<OsStr>>(mut self, recursive: bool, matches: S) -> Self {
+    /// Makes this depend on the files in the base directory matching the
+    /// given pattern, see [`Glob`] for the syntax. Further patterns can be
+    /// added with [`with_include`](Self::with_include) and
+    /// [`with_exclude`](Self::with_exclude).
+    pub fn with_dir(mut self, recursive: bool, pattern: &str) -> Self {
         self.dt = DepType::Dir {
             recursive,
-            pattern: matches.as_ref().to_os_string(),
+            include: vec![Glob::new(pattern)],
+            exclude: Vec::new(),
         };
         self
     }
 
+    /// Also depends on the files matching `pattern`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is not a directory dependency.
+    pub fn with_include(mut self, pattern: &str) -> Self {
+        match &mut self.dt {
+            DepType::File => panic!("include pattern on file dependency {}", self.base.display()),
+            DepType::Dir { include, .. } => include.push(Glob::new(pattern)),
+        }
+        self
+    }
+
+    /// Doesn't depend on the files matching `pattern`, even if they match
+    /// one of the include patterns.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is not a directory dependency.
+    pub fn with_exclude(mut self, pattern: &str) -> Self {
+        match &mut self.dt {
+            DepType::File => panic!("exclude pattern on file dependency {}", self.base.display()),
+            DepType::Dir { exclude, .. } => exclude.push(Glob::new(pattern)),
+        }
+        self
+    }
+
     pub fn base(&self) -> &Path {
         &self.base
     }
 
+    /// Returns whether this depends on a single file instead of files in a
+    /// directory.
+    pub fn is_file(&self) -> bool {
+        matches!(self.dt, DepType::File)
+    }
+
+    /// Returns whether this depends on files in subdirectories of the base
+    /// directory too.
+    pub fn is_recursive(&self) -> bool {
+        matches!(
+            self.dt,
+            DepType::Dir {
+                recursive: true,
+                ..
+            }
+        )
+    }
+
     pub fn paths(&self) -> Paths {
         Paths::new(self)
     }
+
+    /// Returns whether there may be a path that is matched by both this and
+    /// the other dependency.
+    pub fn overlaps(&self, other: &PathDep) -> bool {
+        match (&self.dt, &other.dt) {
+            (DepType::File, _) => other.path_matches(&self.base),
+            (_, DepType::File) => self.path_matches(&other.base),
+            (DepType::Dir { .. }, DepType::Dir { .. }) => {
+                // We can't tell whether two patterns have a common match, so
+                // assume they do if one directory is contained in the other.
+                self.base.starts_with(&other.base) || other.base.starts_with(&self.base)
+            }
+        }
+    }
+}
+
+/// Formats the dependency as its base path, followed by the patterns for
+/// directories, like `src/** [*.java, !Test*]`.
+impl Display for PathDep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.base.display())?;
+
+        if let DepType::Dir {
+            recursive,
+            include,
+            exclude,
+        } = &self.dt
+        {
+            let patterns: Vec<_> = include
+                .iter()
+                .map(|el| el.as_str().to_string())
+                .chain(exclude.iter().map(|el| format!("!{}", el.as_str())))
+                .collect();
+
+            let suffix = if *recursive { "/**" } else { "/" };
+            write!(f, "{} [{}]", suffix, patterns.join(", "))?;
+        }
+
+        if self.optional {
+            write!(f, " (optional)")?;
+        }
+
+        Ok(())
+    }
 }
 
 pub trait PathDepLike {
@@ -119,22 +219,22 @@ impl PathDepLike for PathDep {
     fn path_matches(&self, path: &Path) -> bool {
         match &self.dt {
             DepType::File => self.base == path,
-            DepType::Dir { recursive, pattern } => {
+            DepType::Dir {
+                recursive,
+                include,
+                exclude,
+            } => {
                 let stripped = match path.strip_prefix(&self.base) {
                     Ok(p) => p,
                     Err(_) => return false,
                 };
 
-                if !recursive && stripped.parent().is_some() {
+                if !recursive && stripped.components().count() > 1 {
                     return false;
                 }
 
-                // TODO: don't hardcode this, lmao
-                if pattern.to_str() == Some("*.class") {
-                    stripped.extension().and_then(OsStr::to_str) == Some("class")
-                } else {
-                    todo!()
-                }
+                include.iter().any(|el| el.matches(stripped))
+                    && !exclude.iter().any(|el| el.matches(stripped))
             }
         }
     }
@@ -267,3 +367,52 @@ enum PathsState<'a> {
         stack: Vec<ReadDir>,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{PathDep, PathDepLike};
+
+    #[test]
+    fn multiple_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let names = [
+            "A.java",
+            "B.kt",
+            "C.txt",
+            "sub/D.java",
+            "sub/E.kt",
+            "sub/F.kt",
+        ];
+
+        for name in names {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let dep = PathDep::new(dir.path().to_path_buf())
+            .with_dir(true, "*.java")
+            .with_include("*.kt")
+            .with_exclude("F.*");
+
+        let mut paths: Vec<_> = dep
+            .paths()
+            .map(|el| el.strip_prefix(dir.path()).unwrap().to_path_buf())
+            .collect();
+        paths.sort();
+
+        let expected: Vec<PathBuf> = ["A.java", "B.kt", "sub/D.java", "sub/E.kt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        assert_eq!(paths, expected);
+
+        assert!(dep.path_matches(&dir.path().join("x/G.kt")));
+        assert!(!dep.path_matches(&dir.path().join("x/F.kt")));
+        assert!(!dep.path_matches(&dir.path().join("C.txt")));
+    }
+}
@@ -0,0 +1,505 @@
+use std::collections::VecDeque;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc;
+use std::thread;
+
+use log::warn;
+use petgraph::graph::NodeIndex;
+
+use crate::task::{CycleError, StaleReason, TaskError, TaskGraph};
+use crate::Error;
+
+/// The maximum number of times tasks get run in one build.
+pub const MAX_ROUNDS: usize = 10;
+
+/// Runs the tasks of a [`TaskGraph`] in dependency order, running up to `jobs`
+/// tasks that don't depend on each other at the same time.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    jobs: usize,
+    keep_going: bool,
+    explain: bool,
+    dry_run: bool,
+}
+
+impl Scheduler {
+    pub fn new(jobs: usize) -> Self {
+        Scheduler {
+            jobs: jobs.max(1),
+            keep_going: false,
+            explain: false,
+            dry_run: false,
+        }
+    }
+
+    /// Keep starting tasks that don't depend on a failed task after the first
+    /// failure, instead of waiting for the running tasks and stopping.
+    pub fn with_keep_going(mut self, keep_going: bool) -> Self {
+        self.keep_going = keep_going;
+        self
+    }
+
+    /// Print why each task that runs is out of date.
+    pub fn with_explain(mut self, explain: bool) -> Self {
+        self.explain = explain;
+        self
+    }
+
+    /// Print the commands of the tasks that are out of date instead of
+    /// running them. Since nothing changes, tasks depending on one that
+    /// would run are assumed to be out of date as well.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Runs all tasks in the graph. Tasks can depend on things that aren't
+    /// linked in the graph, like the ABI of classes that depend on each other
+    /// in a cycle, so after all tasks ran, the ones that became out of date
+    /// during the build run again until there are none left.
+    pub fn run(&self, graph: &TaskGraph) -> Result<BuildReport, CycleError> {
+        let order = graph.sorted()?;
+
+        let mut report = BuildReport {
+            failures: Vec::new(),
+            finished: 0,
+            ran: 0,
+            total: graph.len(),
+        };
+
+        let mut ran = vec![false; graph.len()];
+        let mut tasks = order.clone();
+        let mut round = 0;
+
+        loop {
+            self.run_round(graph, &tasks, round > 0, &mut ran, &mut report);
+            round += 1;
+
+            // a dry run doesn't change anything, so another round would
+            // find the same tasks out of date
+            if !report.is_success() || self.dry_run {
+                break;
+            }
+
+            tasks = order
+                .iter()
+                .copied()
+                .filter(|&idx| {
+                    let node = &graph[idx];
+                    node.context().is_stale(node.task())
+                })
+                .collect();
+
+            if tasks.is_empty() {
+                break;
+            }
+
+            if round == MAX_ROUNDS {
+                warn!(
+                    "{} task(s) still out of date after running {} times, giving up",
+                    tasks.len(),
+                    round
+                );
+                break;
+            }
+        }
+
+        report.ran = ran.iter().filter(|&&el| el).count();
+        Ok(report)
+    }
+
+    /// Runs `tasks`, which are sorted in dependency order. `rerun` is set if
+    /// the tasks ran before in this build. The tasks that were out of date
+    /// get marked in `ran`.
+    fn run_round(
+        &self,
+        graph: &TaskGraph,
+        tasks: &[NodeIndex],
+        rerun: bool,
+        ran: &mut [bool],
+        report: &mut BuildReport,
+    ) {
+        let mut is_selected = vec![false; graph.len()];
+
+        for &idx in tasks {
+            is_selected[idx.index()] = true;
+        }
+
+        let selected = |idx: &NodeIndex| is_selected[idx.index()];
+
+        // number of dependencies of each task that haven't finished yet
+        let mut pending = vec![0; graph.len()];
+
+        for &idx in tasks {
+            pending[idx.index()] = graph.dependencies(idx).filter(selected).count();
+        }
+
+        let mut ready: VecDeque<_> = tasks
+            .iter()
+            .copied()
+            .filter(|idx| pending[idx.index()] == 0)
+            .collect();
+
+        let (tx, rx) = mpsc::channel();
+
+        thread::scope(|s| {
+            let mut running = 0;
+            let mut stop = false;
+
+            loop {
+                while !stop && running < self.jobs {
+                    let idx = match ready.pop_front() {
+                        None => break,
+                        Some(idx) => idx,
+                    };
+
+                    let tx = tx.clone();
+                    let explain = self.explain;
+                    let dry_run = self.dry_run;
+                    let upstream: Vec<_> = graph
+                        .dependencies(idx)
+                        .filter(|el| dry_run && ran[el.index()])
+                        .collect();
+                    running += 1;
+
+                    s.spawn(move || {
+                        let node = &graph[idx];
+
+                        // Catch panics so that the result always gets sent,
+                        // otherwise we'd wait for this task forever.
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            let ctx = node.context();
+                            let reason = ctx.stale_reason(node.task()).or_else(|| {
+                                would_change_inputs(graph, idx, &upstream).map(|el| {
+                                    StaleReason::DependencyWouldRun(graph[el].to_string())
+                                })
+                            });
+
+                            let reason = match reason {
+                                Some(reason) => reason,
+                                None if dry_run => return Ok(false),
+                                None => {
+                                    ctx.record_state(node.task())?;
+                                    return Ok(false);
+                                }
+                            };
+
+                            if explain {
+                                println!("{} is out of date: {}", node, reason);
+                            }
+
+                            if dry_run {
+                                // print the task in one go so that the
+                                // output of other tasks doesn't end up in
+                                // between
+                                let mut s = format!("{}\n", node);
+
+                                for command in node.task().commands(&ctx) {
+                                    s.push_str(&format!("    {}\n", command));
+                                }
+
+                                print!("{}", s);
+                                return Ok(true);
+                            }
+
+                            node.task().make(&ctx)?;
+                            ctx.record_state(node.task())?;
+                            Ok(true)
+                        }))
+                        .unwrap_or(Err(TaskError::Panicked));
+
+                        // can't fail, the receiver lives until all tasks are done
+                        let _ = tx.send((idx, result));
+                    });
+                }
+
+                if running == 0 {
+                    break;
+                }
+
+                let (idx, result) = rx.recv().unwrap();
+                running -= 1;
+
+                match result {
+                    Ok(out_of_date) => {
+                        ran[idx.index()] |= out_of_date;
+
+                        // tasks that run again were already counted
+                        if !rerun {
+                            report.finished += 1;
+                        }
+
+                        for dependent in graph.dependents(idx).filter(selected) {
+                            pending[dependent.index()] -= 1;
+
+                            if pending[dependent.index()] == 0 {
+                                ready.push_back(dependent);
+                            }
+                        }
+                    }
+                    Err(source) => {
+                        let node = &graph[idx];
+
+                        if rerun {
+                            report.finished -= 1;
+                        }
+
+                        report.failures.push(Error::Task {
+                            project: node.project().name().to_string(),
+                            task: node.task().name(),
+                            source,
+                        });
+
+                        if !self.keep_going {
+                            stop = true;
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Returns the first task of `upstream` with outputs that the task at `idx`
+/// takes as input. Tasks that only run before it because of the order of
+/// their projects don't change anything it reads.
+fn would_change_inputs(
+    graph: &TaskGraph,
+    idx: NodeIndex,
+    upstream: &[NodeIndex],
+) -> Option<NodeIndex> {
+    let node = &graph[idx];
+    let ctx = node.context();
+    let mut inputs = node.task().inputs(&ctx);
+    inputs.extend(node.task().soft_inputs(&ctx));
+
+    upstream.iter().copied().find(|&el| {
+        let dependency = &graph[el];
+
+        dependency
+            .task()
+            .outputs(&dependency.context())
+            .iter()
+            .any(|output| inputs.iter().any(|input| output.overlaps(input)))
+    })
+}
+
+#[derive(Debug)]
+pub struct BuildReport {
+    pub failures: Vec<Error>,
+    pub finished: usize,
+    /// The number of tasks that were out of date and ran, or would have in a
+    /// dry run.
+    pub ran: usize,
+    pub total: usize,
+}
+
+impl BuildReport {
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    /// Returns the number of tasks that were never started, either because a
+    /// task they depend on failed, or because the build stopped early.
+    pub fn skipped(&self) -> usize {
+        self.total - self.finished - self.failures.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::plugin::Plugin;
+    use crate::project::{load_project, Project};
+    use crate::task::{
+        Context, ExecError, PathDep, Scheduler, StaleReason, Task, TaskError, TaskGraph,
+    };
+    use crate::Result;
+
+    /// The name, input, output and whether a task fails. The files are
+    /// relative to the project directory.
+    type Spec = (&'static str, Option<&'static str>, &'static str, bool);
+
+    /// What the tasks of a build did.
+    #[derive(Debug, Default)]
+    struct Log {
+        started: Mutex<Vec<&'static str>>,
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+    }
+
+    #[derive(Debug)]
+    struct LogTask {
+        spec: Spec,
+        log: Arc<Log>,
+    }
+
+    impl Task for LogTask {
+        fn name(&self) -> String {
+            self.spec.0.to_string()
+        }
+
+        fn make(&self, ctx: &Context) -> Result<(), TaskError> {
+            let (name, _, output, fail) = self.spec;
+            self.log.started.lock().unwrap().push(name);
+
+            let running = self.log.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.log.max_running.fetch_max(running, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(100));
+            self.log.running.fetch_sub(1, Ordering::SeqCst);
+
+            if fail {
+                return Err(ExecError::Failed.into());
+            }
+
+            fs::write(ctx.project().build_root().join(output), name)?;
+            Ok(())
+        }
+
+        fn inputs(&self, ctx: &Context) -> Vec<PathDep> {
+            let dir = ctx.project().build_root();
+            self.spec
+                .1
+                .iter()
+                .map(|el| PathDep::new(dir.join(el)))
+                .collect()
+        }
+
+        fn outputs(&self, ctx: &Context) -> Vec<PathDep> {
+            vec![PathDep::new(ctx.project().build_root().join(self.spec.2))]
+        }
+
+        fn needs_exec(&self, _ctx: &Context) -> Option<StaleReason> {
+            Some(StaleReason::NeverSucceeded)
+        }
+    }
+
+    struct LogPlugin(&'static [Spec], Arc<Log>);
+
+    impl Plugin for LogPlugin {
+        fn create_tasks(&self, _project: &Project) -> Result<Vec<Box<dyn Task>>> {
+            Ok(self
+                .0
+                .iter()
+                .map(|&spec| {
+                    Box::new(LogTask {
+                        spec,
+                        log: self.1.clone(),
+                    }) as Box<dyn Task>
+                })
+                .collect())
+        }
+    }
+
+    fn project(dir: &Path, tasks: &'static [Spec], log: &Arc<Log>) -> Project {
+        fs::write(
+            dir.join("obuild.toml"),
+            "[project]\nname = \"p\"\nsource_root = \".\"\n",
+        )
+        .unwrap();
+
+        let mut project = load_project(dir).unwrap();
+        project.register_plugin(LogPlugin(tasks, log.clone()));
+        project
+    }
+
+    fn graph(project: &Project) -> TaskGraph<'_> {
+        let mut graph = TaskGraph::new();
+        graph.add_project(project).unwrap();
+        graph.link();
+        graph
+    }
+
+    #[test]
+    fn runs_dependencies_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Arc::default();
+        let project = project(
+            dir.path(),
+            &[
+                ("c", Some("y"), "z", false),
+                ("a", None, "x", false),
+                ("b", Some("x"), "y", false),
+            ],
+            &log,
+        );
+
+        let report = Scheduler::new(4).run(&graph(&project)).unwrap();
+
+        assert!(report.is_success());
+        assert_eq!(report.ran, 3);
+        assert_eq!(*log.started.lock().unwrap(), ["a", "b", "c"]);
+        assert_eq!(log.max_running.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn runs_up_to_jobs_tasks_at_once() {
+        const TASKS: &[Spec] = &[
+            ("a", None, "a", false),
+            ("b", None, "b", false),
+            ("c", None, "c", false),
+            ("d", None, "d", false),
+        ];
+
+        for jobs in [1, 2] {
+            let dir = tempfile::tempdir().unwrap();
+            let log = Arc::default();
+            let project = project(dir.path(), TASKS, &log);
+
+            let report = Scheduler::new(jobs).run(&graph(&project)).unwrap();
+
+            assert!(report.is_success());
+            assert_eq!(report.ran, 4);
+            assert_eq!(log.max_running.load(Ordering::SeqCst), jobs);
+        }
+    }
+
+    #[test]
+    fn stops_after_failure_unless_keep_going() {
+        const TASKS: &[Spec] = &[
+            ("a", None, "a", true),
+            ("b", Some("a"), "b", false),
+            ("c", None, "c", false),
+            ("d", None, "d", false),
+        ];
+
+        let run = |keep_going| {
+            let dir = tempfile::tempdir().unwrap();
+            let log = Arc::<Log>::default();
+            let project = project(dir.path(), TASKS, &log);
+
+            let report = Scheduler::new(1)
+                .with_keep_going(keep_going)
+                .run(&graph(&project))
+                .unwrap();
+            let started = log.started.lock().unwrap().clone();
+            (report, started)
+        };
+
+        let (report, started) = run(false);
+
+        // nothing starts after the failure
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(started.last(), Some(&"a"));
+        assert_eq!(report.skipped(), 4 - started.len());
+
+        let (report, mut started) = run(true);
+        started.sort_unstable();
+
+        // only the task depending on the failed one doesn't run
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(started, ["a", "c", "d"]);
+        assert_eq!(report.skipped(), 1);
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+
+/// Why a task is out of date and needs to run.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum StaleReason {
+    /// An input was modified after the oldest output.
+    InputNewer { input: PathBuf, output: PathBuf },
+    /// An input doesn't exist.
+    InputMissing(PathBuf),
+    /// An output doesn't exist.
+    OutputMissing(PathBuf),
+    /// None of the task's inputs exist, so there's nothing to compare the
+    /// outputs against.
+    NoInputs,
+    /// None of the task's outputs exist.
+    NoOutputs,
+    /// The manifest the task reads its dependencies from doesn't exist.
+    ManifestMissing(PathBuf),
+    /// The manifest the task reads its dependencies from is older than one of
+    /// the task's inputs.
+    ManifestOutdated(PathBuf),
+    /// The fingerprint differs from the one recorded when the task last ran.
+    FingerprintChanged,
+    /// A file has different contents than when the task last ran.
+    FileChanged(PathBuf),
+    /// A file the task didn't have when it last ran is now an input or
+    /// output.
+    FileAdded(PathBuf),
+    /// A file the task had when it last ran isn't an input or output
+    /// anymore.
+    FileRemoved(PathBuf),
+    /// The task didn't succeed before, and can't tell from its files whether
+    /// it's up to date.
+    NeverSucceeded,
+    /// A task this one depends on would run in a dry run, which may change
+    /// its inputs.
+    DependencyWouldRun(String),
+    /// Checking whether the task is up to date failed.
+    Error(String),
+}
+
+impl Display for StaleReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            StaleReason::InputNewer { input, output } => write!(
+                f,
+                "input '{}' is newer than output '{}'",
+                input.display(),
+                output.display()
+            ),
+            StaleReason::InputMissing(path) => write!(f, "input '{}' is missing", path.display()),
+            StaleReason::OutputMissing(path) => write!(f, "output '{}' is missing", path.display()),
+            StaleReason::NoInputs => write!(f, "it has no inputs"),
+            StaleReason::NoOutputs => write!(f, "none of its outputs exist"),
+            StaleReason::ManifestMissing(path) => {
+                write!(f, "manifest '{}' is missing", path.display())
+            }
+            StaleReason::ManifestOutdated(path) => {
+                write!(f, "manifest '{}' is older than its inputs", path.display())
+            }
+            StaleReason::FingerprintChanged => write!(f, "its configuration changed"),
+            StaleReason::FileChanged(path) => {
+                write!(f, "'{}' changed since the last run", path.display())
+            }
+            StaleReason::FileAdded(path) => {
+                write!(f, "'{}' was added since the last run", path.display())
+            }
+            StaleReason::FileRemoved(path) => {
+                write!(f, "'{}' was removed since the last run", path.display())
+            }
+            StaleReason::NeverSucceeded => write!(f, "it has not succeeded before"),
+            StaleReason::DependencyWouldRun(task) => write!(f, "'{}' would run before it", task),
+            StaleReason::Error(e) => write!(f, "checking it failed: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for StaleReason {
+    fn from(e: io::Error) -> Self {
+        StaleReason::Error(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::StaleReason;
+    use crate::task::{input_changed, input_changed_multi, PathDep};
+    use crate::testutil::write;
+
+    #[test]
+    fn explains_reasons() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("A.java");
+        let output = dir.path().join("A.class");
+        let manifest = dir.path().join("A.omf");
+        let explain = |reason: Option<StaleReason>| reason.unwrap().to_string();
+
+        write(&input, "");
+
+        assert_eq!(
+            explain(input_changed(&input, &output).unwrap()),
+            format!("output '{}' is missing", output.display())
+        );
+
+        write(&output, "");
+        thread::sleep(Duration::from_millis(20));
+        write(&input, "");
+
+        assert_eq!(
+            explain(input_changed(&input, &output).unwrap()),
+            format!(
+                "input '{}' is newer than output '{}'",
+                input.display(),
+                output.display()
+            )
+        );
+
+        let inputs: [PathDep; 0] = [];
+        assert_eq!(
+            explain(input_changed_multi(&inputs, &[PathDep::new(output)]).unwrap()),
+            "it has no inputs"
+        );
+
+        assert_eq!(
+            explain(Some(StaleReason::ManifestMissing(manifest.clone()))),
+            format!("manifest '{}' is missing", manifest.display())
+        );
+        assert_eq!(
+            explain(Some(StaleReason::FingerprintChanged)),
+            "its configuration changed"
+        );
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+
+use sha2::{Digest, Sha256};
+
+use crate::task::{omake_dir, PathDep, PathDepLike, StaleReason};
+
+/// Files modified less than this long before their state was recorded always
+/// get hashed.
+const RACY_MTIME_WINDOW: Duration = Duration::from_secs(2);
+
+/// The inputs and outputs of a task as they were when it last ran. A task is
+/// up to date as long as the contents of these files stay the same.
+///
+/// Hashing every file on every build would be slow, so each file's
+/// modification time and size get recorded along with its hash. As long as
+/// those match, the file is assumed to be unchanged without hashing it again.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TaskState {
+    /// The hash of the task's fingerprint, see
+    /// [`Task::fingerprint()`](crate::task::Task::fingerprint).
+    fingerprint: Option<String>,
+    inputs: BTreeMap<PathBuf, FileState>,
+    outputs: BTreeMap<PathBuf, FileState>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct FileState {
+    mtime: Duration,
+    len: u64,
+    hash: String,
+}
+
+impl TaskState {
+    /// Records the current state of the given inputs and outputs. Hashes are
+    /// taken from `previous` for files whose metadata didn't change.
+    pub fn capture(
+        fingerprint: Option<&str>,
+        inputs: &[PathDep],
+        outputs: &[PathDep],
+        previous: Option<&TaskState>,
+    ) -> io::Result<Self> {
+        let capture_all = |deps: &[PathDep]| -> io::Result<BTreeMap<PathBuf, FileState>> {
+            let mut map = BTreeMap::new();
+
+            for path in expand(deps)? {
+                let known = previous.and_then(|el| el.get(&path));
+                let state = FileState::capture(&path, known)?;
+                map.insert(path, state);
+            }
+
+            Ok(map)
+        };
+
+        Ok(TaskState {
+            fingerprint: fingerprint.map(hash_fingerprint),
+            inputs: capture_all(inputs)?,
+            outputs: capture_all(outputs)?,
+        })
+    }
+
+    /// Returns why the task is out of date, or `None` if the fingerprint is
+    /// the same as the recorded one and the given inputs and outputs are the
+    /// same files with the same contents as the recorded ones.
+    pub fn stale_reason(
+        &self,
+        fingerprint: Option<&str>,
+        inputs: &[PathDep],
+        outputs: &[PathDep],
+    ) -> io::Result<Option<StaleReason>> {
+        // a missing input or output always needs to be rebuilt
+        if let Some(path) = find_missing(inputs) {
+            return Ok(Some(StaleReason::InputMissing(path)));
+        }
+
+        if let Some(path) = find_missing(outputs) {
+            return Ok(Some(StaleReason::OutputMissing(path)));
+        }
+
+        if self.fingerprint != fingerprint.map(hash_fingerprint) {
+            return Ok(Some(StaleReason::FingerprintChanged));
+        }
+
+        let compare = |deps: &[PathDep], recorded: &BTreeMap<PathBuf, FileState>| {
+            let paths = expand(deps)?;
+
+            // A changed file can change what the other inputs are, so it's
+            // the more useful thing to report.
+            let known = paths.iter().filter(|el| recorded.contains_key(*el));
+
+            if let Some(path) = self.find_changed(known.map(PathBuf::as_path))? {
+                return Ok(Some(StaleReason::FileChanged(path)));
+            }
+
+            if let Some(path) = paths.iter().find(|el| !recorded.contains_key(*el)) {
+                return Ok(Some(StaleReason::FileAdded(path.clone())));
+            }
+
+            Ok(recorded
+                .keys()
+                .find(|el| paths.binary_search(el).is_err())
+                .map(|el| StaleReason::FileRemoved(el.clone())))
+        };
+
+        match compare(inputs, &self.inputs)? {
+            Some(reason) => Ok(Some(reason)),
+            None => compare(outputs, &self.outputs),
+        }
+    }
+
+    /// Returns whether all of `paths` are recorded in this state and still
+    /// have the same contents.
+    pub fn unchanged<'a, I>(&self, paths: I) -> io::Result<bool>
+    where
+        I: IntoIterator<Item = &'a Path>,
+    {
+        Ok(self.find_changed(paths)?.is_none())
+    }
+
+    /// Returns the first of `paths` that isn't recorded in this state or has
+    /// different contents now.
+    fn find_changed<'a, I>(&self, paths: I) -> io::Result<Option<PathBuf>>
+    where
+        I: IntoIterator<Item = &'a Path>,
+    {
+        for path in paths {
+            let recorded = match self.get(path) {
+                None => return Ok(Some(path.to_path_buf())),
+                Some(state) => state,
+            };
+
+            let current = match FileState::capture(path, Some(recorded)) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Some(path.to_path_buf())),
+                r => r?,
+            };
+
+            if current.hash != recorded.hash {
+                return Ok(Some(path.to_path_buf()));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Reads the state from `path`, returning `None` if there is none.
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let mut state = TaskState {
+            fingerprint: None,
+            inputs: BTreeMap::new(),
+            outputs: BTreeMap::new(),
+        };
+
+        for line in content.lines() {
+            if let Some(fingerprint) = line.strip_prefix("= ") {
+                state.fingerprint = Some(fingerprint.to_string());
+                continue;
+            }
+
+            let (map, rest) = if let Some(rest) = line.strip_prefix("<- ") {
+                (&mut state.inputs, rest)
+            } else if let Some(rest) = line.strip_prefix("-> ") {
+                (&mut state.outputs, rest)
+            } else {
+                continue;
+            };
+
+            match FileState::parse(rest) {
+                Some((path, file_state)) => {
+                    map.insert(path, file_state);
+                }
+                None => {
+                    // treat a broken file like a missing one, the task will
+                    // just run again and overwrite it
+                    return Ok(None);
+                }
+            }
+        }
+
+        Ok(Some(state))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "; Origami Task State")?;
+        writeln!(file, "; Do not edit.")?;
+
+        if let Some(fingerprint) = &self.fingerprint {
+            writeln!(file, "= {}", fingerprint)?;
+        }
+
+        for (prefix, map) in [("<-", &self.inputs), ("->", &self.outputs)] {
+            for (path, state) in map {
+                writeln!(
+                    file,
+                    "{} {} {}.{:09} {} {}",
+                    prefix,
+                    state.hash,
+                    state.mtime.as_secs(),
+                    state.mtime.subsec_nanos(),
+                    state.len,
+                    path.to_string_lossy()
+                )?;
+            }
+        }
+
+        file.flush()
+    }
+
+    /// Returns the outputs the task had when this state was recorded.
+    pub fn outputs(&self) -> impl Iterator<Item = &Path> {
+        self.outputs.keys().map(PathBuf::as_path)
+    }
+
+    fn get(&self, path: &Path) -> Option<&FileState> {
+        self.inputs.get(path).or_else(|| self.outputs.get(path))
+    }
+}
+
+impl FileState {
+    fn capture(path: &Path, known: Option<&FileState>) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let len = metadata.len();
+
+        if let Some(known) = known {
+            if known.mtime == mtime && known.len == len {
+                return Ok(known.clone());
+            }
+        }
+
+        // If the file was modified just now, it could be modified again
+        // without its timestamp changing on filesystems with coarse
+        // timestamps. Don't record the timestamp in that case, so that the
+        // file gets hashed again the next time.
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mtime = if now.saturating_sub(mtime) < RACY_MTIME_WINDOW {
+            Duration::ZERO
+        } else {
+            mtime
+        };
+
+        Ok(FileState {
+            mtime,
+            len,
+            hash: hash_file(path)?,
+        })
+    }
+
+    fn parse(s: &str) -> Option<(PathBuf, Self)> {
+        let mut parts = s.splitn(4, ' ');
+        let hash = parts.next()?.to_string();
+        let (secs, nanos) = parts.next()?.split_once('.')?;
+        let mtime = Duration::new(secs.parse().ok()?, nanos.parse().ok()?);
+        let len = parts.next()?.parse().ok()?;
+        let path = PathBuf::from(parts.next()?);
+
+        Some((path, FileState { mtime, len, hash }))
+    }
+}
+
+/// Returns the first file dependency in `deps` that doesn't exist and isn't
+/// optional.
+fn find_missing(deps: &[PathDep]) -> Option<PathBuf> {
+    deps.iter()
+        .find(|el| el.is_file() && !el.optional() && !el.base().exists())
+        .map(|el| el.base().to_path_buf())
+}
+
+/// Returns all the files matched by `deps`, skipping missing optional ones.
+fn expand(deps: &[PathDep]) -> io::Result<Vec<PathBuf>> {
+    let mut vec = Vec::new();
+
+    for dep in deps {
+        for path in dep.paths() {
+            match fs::metadata(&path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound && dep.optional() => continue,
+                r => r?,
+            };
+
+            vec.push(path.into_owned());
+        }
+    }
+
+    vec.sort();
+    vec.dedup();
+
+    Ok(vec)
+}
+
+/// Returns the SHA-256 hash of the contents of the file at `path`.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+fn hash_fingerprint(fingerprint: &str) -> String {
+    to_hex(&Sha256::digest(fingerprint.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns the path of the file the state of the task named `task` is stored
+/// in, unless the task decides otherwise.
+pub fn default_state_path(build_root: &Path, task: &str) -> PathBuf {
+    let name = to_hex(&Sha256::digest(task.as_bytes()));
+    state_dir(build_root).join(name)
+}
+
+/// Returns the directory task states are stored in by default.
+pub fn state_dir(build_root: &Path) -> PathBuf {
+    omake_dir(build_root).join("state")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::TaskState;
+    use crate::task::{input_changed, PathDep, StaleReason};
+    use crate::testutil::write;
+
+    #[test]
+    fn ignores_mtime_of_unchanged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("A.java");
+        let output = dir.path().join("A.class");
+        let inputs = [PathDep::new(input.clone())];
+        let outputs = [PathDep::new(output.clone())];
+
+        write(&input, "class A {}");
+        write(&output, "A");
+
+        let path = dir.path().join("state");
+        TaskState::capture(None, &inputs, &outputs, None)
+            .unwrap()
+            .save(&path)
+            .unwrap();
+        let state = TaskState::load(&path).unwrap().unwrap();
+
+        // like after a checkout that didn't change the file
+        thread::sleep(Duration::from_millis(20));
+        write(&input, "class A {}");
+
+        assert!(matches!(
+            input_changed(&input, &output).unwrap(),
+            Some(StaleReason::InputNewer { .. })
+        ));
+        assert_eq!(state.stale_reason(None, &inputs, &outputs).unwrap(), None);
+
+        write(&input, "class A { int a; }");
+
+        assert_eq!(
+            state.stale_reason(None, &inputs, &outputs).unwrap(),
+            Some(StaleReason::FileChanged(input))
+        );
+    }
+
+    #[test]
+    fn reruns_on_fingerprint_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("A.java");
+        let inputs = [PathDep::new(input.clone())];
+
+        write(&input, "class A {}");
+
+        let state = TaskState::capture(Some("javac A.java"), &inputs, &[], None).unwrap();
+        let reason = |fingerprint| state.stale_reason(fingerprint, &inputs, &[]).unwrap();
+
+        assert_eq!(reason(Some("javac A.java")), None);
+        assert_eq!(
+            reason(Some("javac -g A.java")),
+            Some(StaleReason::FingerprintChanged)
+        );
+        assert_eq!(reason(None), Some(StaleReason::FingerprintChanged));
+    }
+}
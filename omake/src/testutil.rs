@@ -0,0 +1,24 @@
+//! Helpers shared by the unit tests.
+
+use std::fs;
+use std::path::Path;
+
+use crate::java::jvm;
+use crate::task::Scheduler;
+use crate::{load_projects, task_graph};
+
+/// Writes `content` to `path`, creating its parent directories.
+pub fn write(path: &Path, content: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+/// Builds every task in the build root, returning whether all of them
+/// succeeded.
+pub fn build(build_root: &Path) -> bool {
+    let projects = load_projects(build_root).unwrap();
+    let graph = task_graph(&projects, |_| true).unwrap();
+    let success = Scheduler::new(1).run(&graph).unwrap().is_success();
+    jvm::shutdown(&projects);
+    success
+}
@@ -0,0 +1,291 @@
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use inotify::{Event, EventMask, Inotify, WatchDescriptor, WatchMask};
+use log::warn;
+
+use crate::project::Project;
+use crate::task::{omake_dir, Context, PathDep, Scheduler};
+use crate::{build, Result, Selection};
+
+/// How long the inputs have to stay unchanged before a build starts, so that
+/// saving several files at once only causes one build.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+const BUFFER_SIZE: usize = 4096;
+
+/// Builds the selected tasks, then builds them again every time one of their
+/// inputs changes, until interrupted.
+///
+/// The projects stay loaded the whole time, and since the tasks' recorded
+/// state decides what runs, each build only runs the tasks affected by the
+/// change. Compiling in the same process also means the compiler JVM, if
+/// `direct_jvm` is enabled, is only started once.
+pub fn watch(projects: &[Project], selection: &Selection, scheduler: &Scheduler) -> Result<()> {
+    let mut buffer = [0; BUFFER_SIZE];
+
+    loop {
+        // Start watching before building, so that changes made during the
+        // build cause another one.
+        let mut watcher = Watcher::new(projects)?;
+
+        for dir in watched_dirs(projects)? {
+            watcher.add(dir);
+        }
+
+        match build(projects, selection, scheduler) {
+            Ok(_) => {}
+            Err(e) => eprintln!("error: {}", e),
+        }
+
+        // Some tasks only know all of their outputs after running.
+        watcher.ignore_outputs(projects)?;
+
+        println!("watching for changes...");
+
+        let changed = watcher.wait(&mut buffer)?;
+
+        for path in &changed {
+            println!("changed: {}", path.display());
+        }
+    }
+}
+
+/// Returns the directories to watch: those containing the inputs of every
+/// task, and their parents up to the project's source root, so that new
+/// files and directories get noticed as well.
+fn watched_dirs(projects: &[Project]) -> Result<BTreeSet<PathBuf>> {
+    let mut dirs = BTreeSet::new();
+
+    for project in projects {
+        let ctx = Context::new(project);
+
+        for task in project.create_tasks()? {
+            for input in task.inputs(&ctx) {
+                let base = if input.is_file() {
+                    match input.base().parent() {
+                        Some(dir) => dir,
+                        None => continue,
+                    }
+                } else {
+                    input.base()
+                };
+
+                if input.is_recursive() {
+                    collect_subdirs(base, &mut dirs);
+                }
+
+                let source_root = project.source_root();
+
+                for dir in base.ancestors() {
+                    if !dirs.insert(dir.to_path_buf())
+                        || dir == source_root
+                        || !dir.starts_with(source_root)
+                    {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(dirs)
+}
+
+fn collect_subdirs(dir: &Path, dirs: &mut BTreeSet<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        if matches!(entry.file_type(), Ok(ft) if ft.is_dir()) {
+            let path = entry.path();
+            collect_subdirs(&path, dirs);
+            dirs.insert(path);
+        }
+    }
+}
+
+struct Watcher {
+    inotify: Inotify,
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    /// Changes to these are made by the build itself.
+    outputs: Vec<PathDep>,
+    /// Directories only omake writes to.
+    omake_dirs: Vec<PathBuf>,
+}
+
+impl Watcher {
+    fn new(projects: &[Project]) -> Result<Self> {
+        let mut watcher = Watcher {
+            inotify: Inotify::init()?,
+            dirs: HashMap::new(),
+            outputs: Vec::new(),
+            omake_dirs: projects
+                .iter()
+                .map(|el| omake_dir(el.build_root()))
+                .collect(),
+        };
+
+        watcher.ignore_outputs(projects)?;
+        Ok(watcher)
+    }
+
+    /// Ignores changes to the current outputs of every task and the files
+    /// they produced, instead of the ones from before.
+    fn ignore_outputs(&mut self, projects: &[Project]) -> Result<()> {
+        self.outputs.clear();
+
+        for project in projects {
+            let ctx = Context::new(project);
+
+            for task in project.create_tasks()? {
+                self.outputs.extend(task.outputs(&ctx));
+                self.outputs
+                    .extend(task.produced_files(&ctx).into_iter().map(PathDep::new));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add(&mut self, dir: PathBuf) {
+        if self.is_ignored(&dir) {
+            return;
+        }
+
+        let mask = WatchMask::CLOSE_WRITE
+            | WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MOVE
+            | WatchMask::DELETE_SELF
+            | WatchMask::MOVE_SELF
+            | WatchMask::ONLYDIR;
+
+        match self.inotify.add_watch(&dir, mask) {
+            Ok(wd) => {
+                self.dirs.insert(wd, dir);
+            }
+            Err(e) => warn!("failed to watch '{}': {}", dir.display(), e),
+        }
+    }
+
+    /// Blocks until something in the watched directories changes, then
+    /// waits until nothing has changed for [`DEBOUNCE`] and returns the
+    /// changed paths.
+    fn wait(&mut self, buffer: &mut [u8]) -> Result<BTreeSet<PathBuf>> {
+        let mut changed = BTreeSet::new();
+
+        while changed.is_empty() {
+            let events = self.inotify.read_events_blocking(buffer)?;
+            self.collect(events, &mut changed);
+        }
+
+        loop {
+            thread::sleep(DEBOUNCE);
+
+            let mut events = self.inotify.read_events(buffer)?.peekable();
+
+            if events.peek().is_none() {
+                break;
+            }
+
+            self.collect(events, &mut changed);
+        }
+
+        Ok(changed)
+    }
+
+    fn collect<'a, I>(&self, events: I, changed: &mut BTreeSet<PathBuf>)
+    where
+        I: Iterator<Item = Event<&'a OsStr>>,
+    {
+        for event in events {
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                // events got lost, so assume everything changed
+                changed.extend(self.dirs.values().cloned());
+                continue;
+            }
+
+            let dir = match self.dirs.get(&event.wd) {
+                Some(dir) => dir,
+                None => continue,
+            };
+
+            let path = match event.name {
+                Some(name) => dir.join(name),
+                None => dir.clone(),
+            };
+
+            if !self.is_ignored(&path) {
+                changed.insert(path);
+            }
+        }
+    }
+
+    /// Returns whether `path` is something the build changes: an output, or
+    /// a directory outputs are in, which gets created by the build.
+    fn is_ignored(&self, path: &Path) -> bool {
+        let dep = PathDep::new(path.to_path_buf());
+
+        self.omake_dirs.iter().any(|el| path.starts_with(el))
+            || self
+                .outputs
+                .iter()
+                .any(|el| el.overlaps(&dep) || el.base().starts_with(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Watcher;
+    use crate::load_projects;
+    use crate::testutil::{build, write};
+
+    #[test]
+    fn ignores_changes_made_by_the_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let build_root = dir.path().join("build");
+        let p = build_root.join("p");
+
+        write(&build_root.join("obuildroot"), "p\n");
+        write(
+            &p.join("obuild.toml"),
+            "[project]\nname = \"p\"\nsource_root = \"../..\"\n",
+        );
+        write(
+            &dir.path().join("src/p/A.java"),
+            "package p;\npublic class A {}\n",
+        );
+        write(&dir.path().join("resources/a.txt"), "a");
+
+        assert!(build(&build_root));
+
+        let projects = load_projects(&build_root).unwrap();
+        let watcher = Watcher::new(&projects).unwrap();
+
+        let ignored = [
+            "build/p/src/p/A.class",
+            "build/p/src/p/A.omf",
+            // directories outputs get created in
+            "build/p/src",
+            "build/p/libs",
+            "build/p/resources/a.txt",
+            "build/p/.omake/state",
+        ];
+        let watched = ["src/p/A.java", "src/p/B.java", "src", "resources/a.txt"];
+
+        for path in ignored {
+            assert!(watcher.is_ignored(&dir.path().join(path)), "{}", path);
+        }
+
+        for path in watched {
+            assert!(!watcher.is_ignored(&dir.path().join(path)), "{}", path);
+        }
+    }
+}
@@ -34,6 +34,19 @@ pub enum RemoteFile {
     },
 }
 
+impl RemoteFile {
+    /// Returns the name of the file this gets saved as when it's downloaded,
+    /// which is the last segment of its URL's path.
+    pub fn file_name(&self) -> Option<&str> {
+        match self {
+            RemoteFile::Http { source, .. } => {
+                let path = source.split(['?', '#']).next().unwrap();
+                path.rsplit('/').next().filter(|el| !el.is_empty())
+            }
+        }
+    }
+}
+
 #[derive(Debug, BinSerialize, BinDeserialize)]
 pub struct Dependency {
     pub name: String,